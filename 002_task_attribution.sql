@@ -0,0 +1,4 @@
+-- 002_task_attribution.sql
+-- Attribution (not access control): who created and who last updated each task.
+ALTER TABLE tasks ADD COLUMN created_by TEXT NOT NULL DEFAULT 'anonymous';
+ALTER TABLE tasks ADD COLUMN updated_by TEXT NOT NULL DEFAULT 'anonymous';
@@ -0,0 +1,8 @@
+-- 003_task_changes.sql
+-- Append-only change log; `id` doubles as the monotonic change-id used by long-polling.
+CREATE TABLE IF NOT EXISTS task_changes (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    task_id INTEGER NOT NULL,
+    op TEXT NOT NULL,
+    changed_at TEXT NOT NULL DEFAULT (datetime('now'))
+);
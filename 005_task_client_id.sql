@@ -0,0 +1,4 @@
+-- 005_task_client_id.sql
+-- Client-generated id for idempotent sync pushes. Unique per user; NULLs (regular tasks) never conflict.
+ALTER TABLE tasks ADD COLUMN client_id TEXT;
+CREATE UNIQUE INDEX IF NOT EXISTS idx_tasks_created_by_client_id ON tasks(created_by, client_id);
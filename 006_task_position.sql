@@ -0,0 +1,6 @@
+-- 006_task_position.sql
+-- Manual ordering with gaps: a move writes only the moved row (midpoint of its neighbours).
+-- Existing tasks keep their creation order, spaced 1024 apart.
+ALTER TABLE tasks ADD COLUMN position REAL NOT NULL DEFAULT 0;
+UPDATE tasks SET position = id * 1024.0;
+CREATE INDEX IF NOT EXISTS idx_tasks_position ON tasks(position);
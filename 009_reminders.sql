@@ -0,0 +1,12 @@
+-- 009_reminders.sql
+-- Reminder timestamps on a task, consumed by an external notification worker.
+-- remind_at is stored as UTC 'YYYY-MM-DD HH:MM:SS' so it compares directly with datetime('now').
+CREATE TABLE IF NOT EXISTS reminders (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    task_id INTEGER NOT NULL REFERENCES tasks(id) ON DELETE CASCADE,
+    remind_at TEXT NOT NULL,
+    sent_at TEXT,
+    created_at TEXT NOT NULL DEFAULT (datetime('now'))
+);
+CREATE INDEX IF NOT EXISTS idx_reminders_task_id ON reminders(task_id);
+CREATE INDEX IF NOT EXISTS idx_reminders_due ON reminders(remind_at) WHERE sent_at IS NULL;
@@ -0,0 +1,4 @@
+-- 010_task_progress.sql
+-- Partial completion percentage; 100 exactly when the task is completed.
+ALTER TABLE tasks ADD COLUMN progress INTEGER NOT NULL DEFAULT 0 CHECK (progress BETWEEN 0 AND 100);
+UPDATE tasks SET progress = 100 WHERE completed = 1;
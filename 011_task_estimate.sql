@@ -0,0 +1,3 @@
+-- 011_task_estimate.sql
+-- Optional effort estimate, summed by /api/tasks/workload.
+ALTER TABLE tasks ADD COLUMN estimate_minutes INTEGER CHECK (estimate_minutes IS NULL OR estimate_minutes >= 0);
@@ -0,0 +1,3 @@
+-- 012_task_changes_changed_at.sql
+-- Lookups by time for ?modified_since= delta sync.
+CREATE INDEX IF NOT EXISTS idx_task_changes_changed_at ON task_changes(changed_at);
@@ -0,0 +1,7 @@
+-- 014_token_revocations.sql
+-- Users are not stored, so revocations are keyed by JWT subject. Tokens issued at or before
+-- tokens_valid_after (unix seconds) are rejected.
+CREATE TABLE IF NOT EXISTS token_revocations (
+    subject TEXT PRIMARY KEY,
+    tokens_valid_after INTEGER NOT NULL
+);
@@ -0,0 +1,11 @@
+-- 015_task_completed_at.sql
+-- When a task was last completed (NULL while open), kept up to date by a trigger so every write path
+-- sets it. Tasks completed before this migration stay NULL, since their completion time is unknown.
+ALTER TABLE tasks ADD COLUMN completed_at TEXT;
+CREATE TRIGGER IF NOT EXISTS trg_tasks_completed_at
+AFTER UPDATE OF completed ON tasks
+WHEN NEW.completed IS NOT OLD.completed
+BEGIN
+    UPDATE tasks SET completed_at = CASE WHEN NEW.completed THEN datetime('now') ELSE NULL END WHERE id = NEW.id;
+END;
+CREATE INDEX IF NOT EXISTS idx_tasks_completed_at ON tasks(completed_at);
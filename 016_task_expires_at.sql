@@ -0,0 +1,5 @@
+-- 016_task_expires_at.sql
+-- Optional expiry (UTC, same format as created_at), set from ttl_seconds at creation. The expiry
+-- sweeper deletes tasks once it has passed; the partial index keeps its scan to expiring tasks.
+ALTER TABLE tasks ADD COLUMN expires_at TEXT;
+CREATE INDEX IF NOT EXISTS idx_tasks_expires_at ON tasks(expires_at) WHERE expires_at IS NOT NULL;
@@ -0,0 +1,7 @@
+-- 017_pending_tasks_index.sql
+-- "Pending tasks" is the hot list query. This partial index only holds open tasks, so that query
+-- reads just those rows instead of scanning the whole table, and a ?created= range on top of it
+-- becomes an index search (EXPLAIN QUERY PLAN: "SEARCH tasks USING INDEX idx_tasks_pending_created_at
+-- (created_at>? AND created_at<?)"). The list query names it with INDEXED BY, which also requires the
+-- WHERE clause to contain the literal `completed = 0`.
+CREATE INDEX IF NOT EXISTS idx_tasks_pending_created_at ON tasks(created_at) WHERE completed = 0;
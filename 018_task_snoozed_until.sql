@@ -0,0 +1,3 @@
+-- 018_task_snoozed_until.sql
+-- While snoozed_until (UTC, same format as created_at) is in the future the task is hidden from lists.
+ALTER TABLE tasks ADD COLUMN snoozed_until TEXT;
@@ -0,0 +1,13 @@
+-- 019_task_completed_at_on_insert.sql
+-- 015's trigger only fires on UPDATE, so tasks inserted already completed (progress 100 on create,
+-- upserts, batch and write-behind creates) kept completed_at NULL. Stamp those on INSERT too, and
+-- backfill the ones created since 015 was applied; older completions stay unknown.
+CREATE TRIGGER IF NOT EXISTS trg_tasks_completed_at_insert
+AFTER INSERT ON tasks
+WHEN NEW.completed = 1 AND NEW.completed_at IS NULL
+BEGIN
+    UPDATE tasks SET completed_at = datetime('now') WHERE id = NEW.id;
+END;
+UPDATE tasks SET completed_at = created_at
+WHERE completed = 1 AND completed_at IS NULL
+  AND created_at >= (SELECT applied_at FROM _migrations WHERE version = 15);
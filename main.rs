@@ -1,35 +1,560 @@
 \
-use actix_web::{get, post, put, delete, web, App, HttpResponse, HttpServer, Responder, HttpRequest, middleware::Logger};
-use serde::{Deserialize, Serialize};
-use sqlx::{SqlitePool, Row};
+use actix_web::{get, post, put, patch, delete, web, App, HttpResponse, HttpServer, Responder, HttpRequest, HttpMessage, middleware::{Condition, Logger, NormalizePath}};
+use actix_web::body::{EitherBody, MessageBody};
+use actix_web::dev::{ServiceFactory, ServiceRequest, ServiceResponse};
+use actix_web::http::header::{
+    ContentEncoding, HeaderMap, HeaderName, HeaderValue, CONTENT_ENCODING, CONTENT_SECURITY_POLICY, CONTENT_TYPE, LOCATION, REFERRER_POLICY,
+    RETRY_AFTER, X_CONTENT_TYPE_OPTIONS, X_FRAME_OPTIONS,
+};
+use actix_web::middleware::{from_fn, Next};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use sqlx::{Connection, QueryBuilder, Sqlite, SqlitePool, SqliteConnection, Row, ConnectOptions, sqlite::{SqliteConnectOptions, SqliteRow}};
 use thiserror::Error;
-use validator::Validate;
+use schemars::{schema_for, JsonSchema};
+use validator::{Validate, ValidationError, ValidationErrors};
+use futures_util::{future::LocalBoxFuture, StreamExt};
 use std::fs;
 use std::env;
-use chrono::{Utc, DateTime};
+use std::time::{Duration, Instant};
+use std::cell::Cell;
+use std::future::Future;
+use std::rc::Rc;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::str::FromStr;
+use log::LevelFilter;
+use tokio::sync::{broadcast, mpsc, oneshot, Semaphore};
+use aes_gcm::{aead::{rand_core::RngCore, Aead, AeadCore, KeyInit, OsRng}, Aes256Gcm, Nonce};
+use base64::{engine::general_purpose::{STANDARD as BASE64, URL_SAFE_NO_PAD}, Engine};
+use sha2::{Digest, Sha256};
+use chrono::{DateTime, Datelike, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use chrono_tz::Tz;
 use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation, Algorithm};
 
 // ---------- Models ----------
 
 #[derive(Serialize)]
 struct Task {
+    #[serde(serialize_with = "serialize_id")]
     id: i64,
     title: String,
     completed: bool,
     created_at: String,
+    created_by: String,
+    updated_by: String,
+    client_id: Option<String>,
+    position: f64,
+    progress: i64,
+    estimate_minutes: Option<i64>,
+    expires_at: Option<String>,
+    snoozed_until: Option<String>,
 }
 
-#[derive(Deserialize, Validate)]
+/// Set from `STRING_IDS` at startup. Serializers have no access to `AppState`, hence a static.
+static STRING_IDS: AtomicBool = AtomicBool::new(false);
+
+/// Task ids as JSON numbers, or as strings with `STRING_IDS` so JavaScript clients don't lose
+/// precision above 2^53.
+fn serialize_id<S: serde::Serializer>(id: &i64, serializer: S) -> Result<S::Ok, S::Error> {
+    if STRING_IDS.load(Ordering::Relaxed) {
+        serializer.collect_str(id)
+    } else {
+        serializer.serialize_i64(*id)
+    }
+}
+
+/// An id in a request body, accepted as a number or a numeric string regardless of `STRING_IDS`.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum IdRepr {
+    Number(i64),
+    Text(String),
+}
+
+impl IdRepr {
+    fn into_id<E: serde::de::Error>(self) -> Result<i64, E> {
+        match self {
+            IdRepr::Number(id) => Ok(id),
+            IdRepr::Text(text) => text.parse().map_err(|_| E::custom(format!("invalid id '{}'", text))),
+        }
+    }
+}
+
+fn deserialize_opt_id<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Option<i64>, D::Error> {
+    Option::<IdRepr>::deserialize(deserializer)?.map(IdRepr::into_id).transpose()
+}
+
+fn deserialize_ids<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Vec<i64>, D::Error> {
+    Vec::<IdRepr>::deserialize(deserializer)?.into_iter().map(IdRepr::into_id).collect()
+}
+
+const TASK_COLUMNS: &str = "id, title, completed, created_at, created_by, updated_by, client_id, position, progress, estimate_minutes, expires_at, snoozed_until";
+
+/// Spacing between neighbouring positions on insert and after a rebalance.
+const POSITION_GAP: f64 = 1024.0;
+/// SQL expression placing a new task `POSITION_GAP` after every existing one.
+fn next_position_sql() -> String {
+    format!("(SELECT COALESCE(MAX(position), 0) + {} FROM tasks)", POSITION_GAP)
+}
+
+impl Task {
+    /// Maps a `TASK_COLUMNS` row, decrypting the title if it was stored encrypted.
+    fn from_row(rec: &SqliteRow, data: &AppState) -> Result<Task, AppError> {
+        Ok(Task {
+            id: rec.get::<i64, _>("id"),
+            title: decrypt_field(data, &rec.get::<String, _>("title"))?,
+            completed: rec.get::<i64, _>("completed") != 0,
+            created_at: rec.get::<String, _>("created_at"),
+            created_by: rec.get::<String, _>("created_by"),
+            updated_by: rec.get::<String, _>("updated_by"),
+            client_id: rec.get::<Option<String>, _>("client_id"),
+            position: rec.get::<f64, _>("position"),
+            progress: rec.get::<i64, _>("progress"),
+            estimate_minutes: rec.get::<Option<i64>, _>("estimate_minutes"),
+            expires_at: rec.get::<Option<String>, _>("expires_at"),
+            snoozed_until: rec.get::<Option<String>, _>("snoozed_until"),
+        })
+    }
+
+    /// Rewrites `created_at`, `expires_at` and `snoozed_until` (stored as UTC `YYYY-MM-DD HH:MM:SS`) as RFC 3339 in `tz`.
+    fn in_timezone(mut self, tz: Option<Tz>) -> Task {
+        if let Some(tz) = tz {
+            let convert = |stored: &mut String| {
+                if let Ok(utc) = NaiveDateTime::parse_from_str(stored, "%Y-%m-%d %H:%M:%S") {
+                    *stored = utc.and_utc().with_timezone(&tz).to_rfc3339();
+                }
+            };
+            convert(&mut self.created_at);
+            for stored in [&mut self.expires_at, &mut self.snoozed_until].into_iter().flatten() {
+                convert(stored);
+            }
+        }
+        self
+    }
+}
+
+/// Parses a `?tz=` IANA timezone name such as `America/New_York`. `None` keeps the stored UTC value.
+fn parse_tz(raw: Option<&str>) -> Result<Option<Tz>, AppError> {
+    raw.map(|name| name.parse::<Tz>().map_err(|_| AppError::BadRequest(format!("unknown timezone '{}'", name))))
+        .transpose()
+}
+
+#[derive(Deserialize, Validate, JsonSchema)]
 struct CreateTask {
     #[validate(length(min = 1, message = "title cannot be empty"))]
     title: String,
+    #[validate(range(min = 0, max = 100, message = "progress must be between 0 and 100"))]
+    progress: Option<i64>,
+    #[validate(range(min = 0, message = "estimate_minutes cannot be negative"))]
+    estimate_minutes: Option<i64>,
+    /// Delete the task automatically this many seconds after creation.
+    #[validate(range(min = 1, message = "ttl_seconds must be positive"))]
+    ttl_seconds: Option<i64>,
+}
+
+/// A task plus any related collections requested via `?include=`.
+#[derive(Serialize)]
+struct TaskDetail {
+    #[serde(flatten)]
+    task: Task,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    attachments: Option<Vec<Attachment>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    comment_count: Option<i64>,
+}
+
+#[derive(Deserialize)]
+struct CreateTaskQuery {
+    /// Create only if the caller has no task with exactly this title yet.
+    unique_title: Option<bool>,
+}
+
+#[derive(Deserialize)]
+struct GetTaskQuery {
+    include: Option<String>,
+    tz: Option<String>,
+}
+
+/// Related collections that can be embedded via `?include=a,b`.
+const KNOWN_INCLUDES: &[&str] = &["attachments", "comment_count"];
+
+#[derive(Default)]
+struct Includes {
+    attachments: bool,
+    comment_count: bool,
+}
+
+impl Includes {
+    fn parse(raw: Option<&str>) -> Result<Includes, AppError> {
+        let mut includes = Includes::default();
+        for name in raw.unwrap_or("").split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            match name {
+                "attachments" => includes.attachments = true,
+                "comment_count" => includes.comment_count = true,
+                other => return Err(AppError::BadRequest(format!(
+                    "unknown include '{}' (supported: {})", other, KNOWN_INCLUDES.join(", ")
+                ))),
+            }
+        }
+        Ok(includes)
+    }
 }
 
 #[derive(Deserialize, Validate)]
+struct UpsertTask {
+    #[validate(length(min = 1, message = "title cannot be empty"))]
+    title: String,
+    completed: Option<bool>,
+    #[validate(range(min = 0, max = 100, message = "progress must be between 0 and 100"))]
+    progress: Option<i64>,
+}
+
+/// Which tasks a list shows by completion state.
+#[derive(Clone, Copy)]
+enum CompletedFilter {
+    All,
+    OnlyPending,
+    OnlyCompleted,
+}
+
+impl FromStr for CompletedFilter {
+    type Err = String;
+
+    /// Accepts the `DEFAULT_LIST_COMPLETED` names as well as the `?completed=true|false|all` query values.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "all" => Ok(CompletedFilter::All),
+            "only_pending" | "false" => Ok(CompletedFilter::OnlyPending),
+            "only_completed" | "true" => Ok(CompletedFilter::OnlyCompleted),
+            other => Err(format!("invalid completed filter '{}' (expected true, false or all)", other)),
+        }
+    }
+}
+
+/// Columns `DEFAULT_SORT` and `?sort=` may order task lists by.
+const SORT_COLUMNS: &[&str] = &["id", "created_at", "title", "position", "progress", "estimate_minutes"];
+
+/// Task list order, written `column` (ascending) or `column:asc|desc`.
+#[derive(Clone, Copy)]
+struct ListSort {
+    column: &'static str,
+    descending: bool,
+}
+
+impl FromStr for ListSort {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (column, direction) = s.trim().split_once(':').unwrap_or((s.trim(), "asc"));
+        let column = SORT_COLUMNS.iter().copied().find(|c| c.eq_ignore_ascii_case(column)).ok_or_else(|| {
+            format!("invalid sort column '{}' (expected one of: {})", column, SORT_COLUMNS.join(", "))
+        })?;
+        let descending = match direction.to_lowercase().as_str() {
+            "asc" => false,
+            "desc" => true,
+            other => return Err(format!("invalid sort direction '{}' (expected asc or desc)", other)),
+        };
+        Ok(ListSort { column, descending })
+    }
+}
+
+impl ListSort {
+    /// Appends the `ORDER BY` clause; ties are broken by id in the same direction so pages are stable.
+    fn push_order_by(self, qb: &mut QueryBuilder<'_, Sqlite>) {
+        let direction = if self.descending { "DESC" } else { "ASC" };
+        qb.push(format_args!(" ORDER BY {} {}", self.column, direction));
+        if self.column != "id" {
+            qb.push(format_args!(", id {}", direction));
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct ListQuery {
+    completed: Option<String>,
+    /// Overrides `DEFAULT_SORT`, e.g. `created_at:desc`.
+    sort: Option<String>,
+    /// `today`, `yesterday` or `this_week` (from Monday), evaluated in `tz` (default UTC).
+    created: Option<String>,
+    min_progress: Option<i64>,
+    max_progress: Option<i64>,
+    tz: Option<String>,
+    /// Overrides `ENVELOPE_RESPONSES` for this request.
+    envelope: Option<bool>,
+    /// RFC 3339 timestamp; switches `list_tasks` to a delta response (see `list_tasks_modified_since`).
+    modified_since: Option<DateTime<Utc>>,
+    /// Also list tasks whose `snoozed_until` is still in the future.
+    include_snoozed: Option<bool>,
+}
+
+/// Appends the `ORDER BY` clause for a list. An explicit `?sort=` wins over `DEFAULT_SORT`.
+fn push_list_order(qb: &mut QueryBuilder<'_, Sqlite>, query: &ListQuery, data: &AppState) -> Result<(), AppError> {
+    let sort = match &query.sort {
+        Some(raw) => raw.parse::<ListSort>().map_err(AppError::BadRequest)?,
+        None => data.default_sort,
+    };
+    sort.push_order_by(qb);
+    Ok(())
+}
+
+/// Appends the `WHERE` clause for list filters, directly after `FROM tasks`. An explicit `?completed=`
+/// wins over `DEFAULT_LIST_COMPLETED`.
+fn push_list_filters(qb: &mut QueryBuilder<'_, Sqlite>, query: &ListQuery, data: &AppState) -> Result<(), AppError> {
+    let completed = match &query.completed {
+        Some(raw) => raw.parse::<CompletedFilter>().map_err(AppError::BadRequest)?,
+        None => data.default_list_completed,
+    };
+    // Without ANALYZE statistics the planner prefers a full scan in id order, so pending lists
+    // name their partial index explicitly. It requires the literal `completed = 0` below.
+    if let CompletedFilter::OnlyPending = completed {
+        qb.push(" INDEXED BY idx_tasks_pending_created_at");
+    }
+    qb.push(" WHERE 1 = 1");
+    match completed {
+        CompletedFilter::All => {}
+        CompletedFilter::OnlyPending => { qb.push(" AND completed = 0"); }
+        CompletedFilter::OnlyCompleted => { qb.push(" AND completed = 1"); }
+    }
+    for (name, bound) in [("min_progress", query.min_progress), ("max_progress", query.max_progress)] {
+        if bound.is_some_and(|b| !(0..=100).contains(&b)) {
+            return Err(AppError::BadRequest(format!("{} must be between 0 and 100", name)));
+        }
+    }
+    if let Some(min) = query.min_progress {
+        qb.push(" AND progress >= ").push_bind(min);
+    }
+    if let Some(max) = query.max_progress {
+        qb.push(" AND progress <= ").push_bind(max);
+    }
+    // Delta sync always includes snoozed tasks: no change is recorded when a snooze runs out, so a
+    // client that never received the task would not learn about it later.
+    if !query.include_snoozed.unwrap_or(false) && query.modified_since.is_none() {
+        qb.push(" AND (snoozed_until IS NULL OR snoozed_until <= datetime('now'))");
+    }
+    if let Some(keyword) = &query.created {
+        let (start, end) = created_range(keyword, parse_tz(query.tz.as_deref())?.unwrap_or(Tz::UTC))?;
+        qb.push(" AND created_at >= ").push_bind(start);
+        qb.push(" AND created_at < ").push_bind(end);
+    }
+    Ok(())
+}
+
+/// The `[start, end)` UTC range, in the stored `created_at` format, for a `?created=` keyword.
+/// Day boundaries are local midnights in `tz`.
+fn created_range(keyword: &str, tz: Tz) -> Result<(String, String), AppError> {
+    let today = Utc::now().with_timezone(&tz).date_naive();
+    let (first, days) = match keyword {
+        "today" => (today, 1),
+        "yesterday" => (today - chrono::Duration::days(1), 1),
+        "this_week" => (today - chrono::Duration::days(today.weekday().num_days_from_monday() as i64), 7),
+        other => return Err(AppError::BadRequest(format!(
+            "invalid created filter '{}' (expected today, yesterday or this_week)", other
+        ))),
+    };
+    Ok((local_midnight_utc(first, tz), local_midnight_utc(first + chrono::Duration::days(days), tz)))
+}
+
+/// Local midnight of `day` in `tz` as a UTC timestamp in the stored format.
+fn local_midnight_utc(day: NaiveDate, tz: Tz) -> String {
+    let midnight = day.and_hms_opt(0, 0, 0).expect("midnight is valid");
+    // On a DST gap midnight may not exist locally; fall back to treating it as UTC.
+    tz.from_local_datetime(&midnight).earliest()
+        .map(|t| t.naive_utc())
+        .unwrap_or(midnight)
+        .format("%Y-%m-%d %H:%M:%S")
+        .to_string()
+}
+
+#[derive(Deserialize)]
+struct PageQuery {
+    limit: Option<i64>,
+    offset: Option<i64>,
+}
+
+const DEFAULT_PAGE_LIMIT: i64 = 50;
+const MAX_PAGE_LIMIT: i64 = 200;
+
+impl PageQuery {
+    /// Returns `(limit, offset)` with defaults applied.
+    fn resolve(&self) -> Result<(i64, i64), AppError> {
+        let limit = self.limit.unwrap_or(DEFAULT_PAGE_LIMIT);
+        let offset = self.offset.unwrap_or(0);
+        if !(1..=MAX_PAGE_LIMIT).contains(&limit) {
+            return Err(AppError::BadRequest(format!("limit must be between 1 and {}", MAX_PAGE_LIMIT)));
+        }
+        if offset < 0 {
+            return Err(AppError::BadRequest("offset cannot be negative".into()));
+        }
+        Ok((limit, offset))
+    }
+}
+
+/// Pagination envelope for list responses.
+#[derive(Serialize)]
+struct Page<T> {
+    items: Vec<T>,
+    total: i64,
+    limit: i64,
+    offset: i64,
+}
+
+#[derive(Deserialize, Validate, JsonSchema)]
 struct UpdateTask {
     #[validate(length(min = 1, message = "title cannot be empty"))]
     title: Option<String>,
     completed: Option<bool>,
+    #[validate(range(min = 0, max = 100, message = "progress must be between 0 and 100"))]
+    progress: Option<i64>,
+    #[validate(range(min = 0, message = "estimate_minutes cannot be negative"))]
+    estimate_minutes: Option<i64>,
+}
+
+/// Field changes for `apply_task_update`; `None` keeps the current value.
+struct TaskChanges {
+    title: Option<String>,
+    completed: Option<bool>,
+    progress: Option<i64>,
+    /// `Some(None)` clears the estimate (PATCH `remove`).
+    estimate_minutes: Option<Option<i64>>,
+}
+
+/// Task fields a client can change, as named in `USER_EDITABLE_FIELDS`. `position` is changed by
+/// `/move` and `snoozed_until` by `/snooze`.
+const EDITABLE_FIELDS: &[&str] = &["title", "completed", "progress", "estimate_minutes", "position", "snoozed_until"];
+
+impl TaskChanges {
+    /// Fields whose requested value differs from `current`. Values derived from others (progress
+    /// set to 100 by completing) don't count.
+    fn modified_fields(&self, current: &Task) -> Vec<&'static str> {
+        let mut fields = Vec::new();
+        if self.title.as_ref().is_some_and(|t| *t != current.title) {
+            fields.push("title");
+        }
+        if self.completed.is_some_and(|c| c != current.completed) {
+            fields.push("completed");
+        }
+        if self.progress.is_some_and(|p| p != current.progress) {
+            fields.push("progress");
+        }
+        if self.estimate_minutes.is_some_and(|e| e != current.estimate_minutes) {
+            fields.push("estimate_minutes");
+        }
+        fields
+    }
+}
+
+/// With `USER_EDITABLE_FIELDS`, only `ADMIN_USERS` may change fields outside that list (403).
+fn check_editable(data: &AppState, actor: &str, fields: &[&str]) -> Result<(), AppError> {
+    let Some(allowed) = &data.user_editable_fields else {
+        return Ok(());
+    };
+    if fields.iter().any(|f| !allowed.contains(f)) && !data.admin_users.iter().any(|a| a == actor) {
+        return Err(AppError::Forbidden);
+    }
+    Ok(())
+}
+
+/// `check_editable` and `check_not_locked` for handlers that write one task field outside
+/// `TaskChanges` (`/move`, `/snooze`); 404 if the task doesn't exist.
+async fn check_field_writable(
+    conn: &mut SqliteConnection,
+    data: &AppState,
+    actor: &str,
+    id: i64,
+    field: &'static str,
+) -> Result<(), AppError> {
+    let rec = sqlx::query(&format!("SELECT {} FROM tasks WHERE id = ?", TASK_COLUMNS))
+        .bind(id)
+        .fetch_optional(conn).await
+        .map_err(|e| AppError::Internal(e.to_string()))?
+        .ok_or(AppError::NotFound)?;
+    let current = Task::from_row(&rec, data)?;
+    check_editable(data, actor, &[field])?;
+    check_not_locked(data, &current, &[field])
+}
+
+/// Keeps `progress` and `completed` in step: progress 100 means completed and completing sets 100.
+/// Un-completing resets progress to 0 unless a progress is given. Both given but contradicting is a 400.
+fn resolve_progress(
+    current_completed: bool,
+    current_progress: i64,
+    completed: Option<bool>,
+    progress: Option<i64>,
+) -> Result<(bool, i64), AppError> {
+    match (completed, progress) {
+        (Some(c), Some(p)) if c != (p == 100) => Err(field_error(
+            "completed", "progress", "completed must be true exactly when progress is 100",
+        )),
+        (_, Some(p)) => Ok((p == 100, p)),
+        (Some(true), None) => Ok((true, 100)),
+        (Some(false), None) if current_completed => Ok((false, 0)),
+        (Some(false), None) => Ok((false, current_progress)),
+        (None, None) => Ok((current_completed, current_progress)),
+    }
+}
+
+/// How titles containing ASCII control characters (other than newline and tab) are handled.
+#[derive(Clone, Copy, PartialEq)]
+enum ControlCharMode {
+    Reject,
+    Strip,
+}
+
+impl FromStr for ControlCharMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "reject" => Ok(ControlCharMode::Reject),
+            "strip" => Ok(ControlCharMode::Strip),
+            other => Err(format!("invalid TITLE_CONTROL_CHARS '{}' (expected reject or strip)", other)),
+        }
+    }
+}
+
+fn is_disallowed_control(c: char) -> bool {
+    c.is_ascii_control() && c != '\n' && c != '\t'
+}
+
+/// Rules `SOFT_VALIDATION` can downgrade from a 422 to a warning on `POST /api/tasks`.
+const SOFT_RULES: &[&str] = &["control_characters", "max_title_len"];
+
+/// Runtime title checks layered on top of the `#[validate]` rules: control characters
+/// (per `TITLE_CONTROL_CHARS`) and `MAX_TITLE_LEN`. Returns the title to store.
+fn clean_title(data: &AppState, title: &str) -> Result<String, AppError> {
+    clean_title_with_warnings(data, title, None)
+}
+
+/// `clean_title` where, given `warnings`, a failed rule listed in `SOFT_VALIDATION` adds its
+/// message there and the title is kept as sent instead of being rejected.
+fn clean_title_with_warnings(data: &AppState, title: &str, mut warnings: Option<&mut Vec<String>>) -> Result<String, AppError> {
+    let mut soft = |rule: &str, code: &'static str, message: String| match warnings.as_deref_mut() {
+        Some(warnings) if data.soft_validation.contains(&rule) => {
+            warnings.push(message);
+            Ok(())
+        }
+        _ => Err(field_error("title", code, message)),
+    };
+    let title = if title.chars().any(is_disallowed_control) {
+        if data.title_control_chars == ControlCharMode::Reject {
+            soft("control_characters", "control_characters", "title cannot contain control characters".into())?;
+            title.to_string()
+        } else {
+            let stripped: String = title.chars().filter(|c| !is_disallowed_control(*c)).collect();
+            if stripped.is_empty() {
+                return Err(field_error("title", "length", "title cannot be empty"));
+            }
+            stripped
+        }
+    } else {
+        title.to_string()
+    };
+    // Title max length is runtime config (`MAX_TITLE_LEN`), so it can't be a `#[validate]` attribute.
+    if title.chars().count() > data.max_title_len {
+        soft("max_title_len", "length", format!("title cannot be longer than {} characters", data.max_title_len))?;
+    }
+    Ok(title)
 }
 
 // ---------- JWT ----------
@@ -38,23 +563,265 @@ struct UpdateTask {
 struct Claims {
     sub: String,
     exp: usize,
+    /// Issued-at, checked against `token_revocations`. Tokens from before it was added count as 0.
+    #[serde(default)]
+    iat: usize,
+    /// Set from `JWT_ISSUER` / `JWT_AUDIENCE` when configured, and then required on every token.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    iss: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    aud: Option<String>,
 }
 
-async fn ensure_auth(req: &HttpRequest, data: &AppState) -> Result<(), AppError> {
+/// Checks the request's credentials and returns the acting subject, used for attribution.
+/// An `X-Api-Key` header is accepted as an alternative to the Bearer token. Unauthenticated
+/// requests act as `ANONYMOUS_SUBJECT`.
+async fn ensure_auth(req: &HttpRequest, data: &AppState) -> Result<String, AppError> {
     if !data.jwt_enabled {
-        return Ok(());
+        return Ok(data.anonymous_subject.clone());
+    }
+    if let Some(key) = req.headers().get("x-api-key") {
+        let key = key.to_str().map_err(|_| AppError::Unauthorized)?;
+        return api_key_subject(req, data, key).await;
     }
     // Allow GET endpoints without auth if read-only is true
     if data.read_only_without_jwt && req.method() == "GET" {
-        return Ok(());
+        return Ok(data.anonymous_subject.clone());
+    }
+    bearer_subject(req, data).await
+}
+
+/// Admin endpoints always require a valid token whose subject is listed in `ADMIN_USERS`,
+/// regardless of method or `READ_ONLY_WITHOUT_JWT`. They are unavailable when JWT is disabled.
+async fn ensure_admin(req: &HttpRequest, data: &AppState) -> Result<String, AppError> {
+    if !data.jwt_enabled {
+        return Err(AppError::Forbidden);
+    }
+    let sub = bearer_subject(req, data).await?;
+    if !data.admin_users.contains(&sub) {
+        return Err(AppError::Forbidden);
+    }
+    Ok(sub)
+}
+
+/// The subject of the request's credentials if any are presented and valid, else `ANONYMOUS_SUBJECT`.
+/// Never an error, for reads that only need to know who is asking.
+async fn request_subject(req: &HttpRequest, data: &AppState) -> String {
+    if !data.jwt_enabled {
+        return data.anonymous_subject.clone();
     }
-    let auth = req.headers().get("authorization").and_then(|v| v.to_str().ok()).unwrap_or("");
-    let token = auth.strip_prefix("Bearer ").ok_or(AppError::Unauthorized)?;
+    let subject = match req.headers().get("x-api-key").map(|key| key.to_str()) {
+        Some(Ok(key)) => api_key_subject(req, data, key).await.ok(),
+        Some(Err(_)) => None,
+        None => bearer_subject(req, data).await.ok(),
+    };
+    subject.unwrap_or_else(|| data.anonymous_subject.clone())
+}
+
+async fn bearer_subject(req: &HttpRequest, data: &AppState) -> Result<String, AppError> {
+    let auth = req.headers().get(&data.auth_header).and_then(|v| v.to_str().ok()).unwrap_or("");
+    let token = auth.strip_prefix(data.auth_scheme.as_str()).ok_or(AppError::Unauthorized)?;
+    Ok(decode_token(token, data).await?.sub)
+}
+
+/// Verifies signature, expiry, `JWT_ISSUER`/`JWT_AUDIENCE` and revocation; any failure is `Unauthorized`.
+async fn decode_token(token: &str, data: &AppState) -> Result<Claims, AppError> {
     let key = DecodingKey::from_secret(data.jwt_secret.as_ref().expect("jwt enabled").as_bytes());
     let mut validation = Validation::new(Algorithm::HS256);
     validation.validate_exp = true;
-    decode::<Claims>(token, &key, &validation).map_err(|_| AppError::Unauthorized)?;
-    Ok(())
+    let mut required = vec!["exp"];
+    if let Some(issuer) = &data.jwt_issuer {
+        validation.set_issuer(&[issuer]);
+        required.push("iss");
+    }
+    match &data.jwt_audience {
+        Some(audience) => {
+            validation.set_audience(&[audience]);
+            required.push("aud");
+        }
+        None => validation.validate_aud = false,
+    }
+    validation.set_required_spec_claims(&required);
+    let token_data = decode::<Claims>(token, &key, &validation).map_err(|_| AppError::Unauthorized)?;
+    let valid_after: Option<i64> = sqlx::query_scalar("SELECT tokens_valid_after FROM token_revocations WHERE subject = ?")
+        .bind(&token_data.claims.sub)
+        .fetch_optional(&data.pool).await
+        .map_err(|e| AppError::Internal(e.to_string()))?;
+    if valid_after.is_some_and(|after| token_data.claims.iat as i64 <= after) {
+        return Err(AppError::Unauthorized);
+    }
+    Ok(token_data.claims)
+}
+
+#[derive(Deserialize)]
+struct IntrospectBody {
+    token: String,
+}
+
+/// RFC 7662-style introspection: whether `token` would be accepted right now, and its claims if so.
+/// Needs no credentials itself, so it is limited per client IP (`INTROSPECT_RATE_LIMIT`).
+/// Invalid, expired or revoked tokens give `{"active": false}` rather than an error.
+#[post("/api/token/introspect")]
+async fn introspect_token(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    body: web::Json<IntrospectBody>,
+) -> Result<impl Responder, AppError> {
+    let key = req.peer_addr().map(|a| a.ip().to_string()).unwrap_or_else(|| "unknown".into());
+    let decision = data.introspect_rate_limiter.check(&key);
+    if !decision.allowed && data.rate_limit_mode == RateLimitMode::Enforce {
+        let mut res = HttpResponse::TooManyRequests().json(serde_json::json!({ "error": "Too Many Requests" }));
+        rate_limit_headers(res.headers_mut(), &decision);
+        res.headers_mut().insert(RETRY_AFTER, HeaderValue::from(decision.retry_after_secs));
+        return Ok(res);
+    }
+    let claims = if data.jwt_enabled { decode_token(body.token.trim(), &data).await.ok() } else { None };
+    let body = match claims {
+        Some(claims) => {
+            // Claims skip unset `iss`/`aud`, so they are omitted here too.
+            let mut body = serde_json::to_value(&claims).map_err(|e| AppError::Internal(e.to_string()))?;
+            body["active"] = true.into();
+            body["token_type"] = "Bearer".into();
+            body
+        }
+        None => serde_json::json!({ "active": false }),
+    };
+    let mut res = HttpResponse::Ok().json(body);
+    rate_limit_headers(res.headers_mut(), &decision);
+    Ok(res)
+}
+
+/// Invalidates every token issued to `{username}` so far; tokens from later logins keep working.
+/// `iat` has one-second resolution, so a login in the same second as the revocation is rejected too.
+/// API keys for the subject are not affected; revoke them separately.
+#[post("/api/admin/users/{username}/revoke-tokens")]
+async fn revoke_user_tokens(
+    req: HttpRequest,
+    path: web::Path<String>,
+    data: web::Data<AppState>,
+) -> Result<impl Responder, AppError> {
+    ensure_admin(&req, &data).await?;
+    let subject = path.into_inner();
+    let valid_after = Utc::now().timestamp();
+    sqlx::query(
+        "INSERT INTO token_revocations (subject, tokens_valid_after) VALUES (?, ?) \
+         ON CONFLICT(subject) DO UPDATE SET tokens_valid_after = excluded.tokens_valid_after"
+    )
+    .bind(&subject)
+    .bind(valid_after)
+    .execute(&data.pool).await
+    .map_err(|e| AppError::Internal(e.to_string()))?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "subject": subject,
+        "tokens_valid_after": DateTime::<Utc>::from_timestamp(valid_after, 0).map(|t| t.to_rfc3339()),
+    })))
+}
+
+// ---------- API keys ----------
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+enum ApiKeyScope {
+    /// GET requests only
+    Read,
+    ReadWrite,
+}
+
+impl ApiKeyScope {
+    fn as_str(self) -> &'static str {
+        match self {
+            ApiKeyScope::Read => "read",
+            ApiKeyScope::ReadWrite => "read_write",
+        }
+    }
+}
+
+/// Keys are random 256-bit secrets, so an unsalted SHA-256 is enough to keep them unusable if the DB leaks.
+fn hash_api_key(key: &str) -> String {
+    Sha256::digest(key.as_bytes()).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+async fn api_key_subject(req: &HttpRequest, data: &AppState, key: &str) -> Result<String, AppError> {
+    let rec = sqlx::query("SELECT subject, scope FROM api_keys WHERE key_hash = ? AND revoked_at IS NULL")
+        .bind(hash_api_key(key))
+        .fetch_optional(&data.pool).await
+        .map_err(|e| AppError::Internal(e.to_string()))?
+        .ok_or(AppError::Unauthorized)?;
+    if rec.get::<String, _>("scope") == ApiKeyScope::Read.as_str() && req.method() != "GET" {
+        return Err(AppError::Forbidden);
+    }
+    Ok(rec.get::<String, _>("subject"))
+}
+
+#[derive(Deserialize, Validate)]
+struct CreateApiKey {
+    #[validate(length(min = 1, message = "subject cannot be empty"))]
+    subject: String,
+    scope: ApiKeyScope,
+}
+
+#[derive(Serialize)]
+struct ApiKeyInfo {
+    id: i64,
+    subject: String,
+    scope: String,
+    created_at: String,
+    revoked_at: Option<String>,
+}
+
+/// Creates a key for `subject`. The plaintext key is only ever returned here.
+#[post("/api/admin/api-keys")]
+async fn create_api_key(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    payload: web::Json<CreateApiKey>,
+) -> Result<impl Responder, AppError> {
+    ensure_admin(&req, &data).await?;
+    payload.validate().map_err(AppError::Validation)?;
+    let mut secret = [0u8; 32];
+    OsRng.fill_bytes(&mut secret);
+    let key = format!("tk_{}", URL_SAFE_NO_PAD.encode(secret));
+    let id: i64 = sqlx::query_scalar("INSERT INTO api_keys (key_hash, subject, scope) VALUES (?, ?, ?) RETURNING id")
+        .bind(hash_api_key(&key))
+        .bind(&payload.subject)
+        .bind(payload.scope.as_str())
+        .fetch_one(&data.pool).await
+        .map_err(|e| AppError::Internal(e.to_string()))?;
+    Ok(HttpResponse::Created().json(serde_json::json!({
+        "id": id,
+        "key": key,
+        "subject": payload.subject,
+        "scope": payload.scope,
+    })))
+}
+
+#[get("/api/admin/api-keys")]
+async fn list_api_keys(req: HttpRequest, data: web::Data<AppState>) -> Result<impl Responder, AppError> {
+    ensure_admin(&req, &data).await?;
+    let rows = sqlx::query("SELECT id, subject, scope, created_at, revoked_at FROM api_keys ORDER BY id ASC")
+        .fetch_all(&data.pool).await
+        .map_err(|e| AppError::Internal(e.to_string()))?;
+    let keys: Vec<ApiKeyInfo> = rows.into_iter().map(|rec| ApiKeyInfo {
+        id: rec.get::<i64, _>("id"),
+        subject: rec.get::<String, _>("subject"),
+        scope: rec.get::<String, _>("scope"),
+        created_at: rec.get::<String, _>("created_at"),
+        revoked_at: rec.get::<Option<String>, _>("revoked_at"),
+    }).collect();
+    Ok(HttpResponse::Ok().json(keys))
+}
+
+#[delete("/api/admin/api-keys/{id}")]
+async fn revoke_api_key(req: HttpRequest, path: web::Path<i64>, data: web::Data<AppState>) -> Result<impl Responder, AppError> {
+    ensure_admin(&req, &data).await?;
+    let res = sqlx::query("UPDATE api_keys SET revoked_at = datetime('now') WHERE id = ? AND revoked_at IS NULL")
+        .bind(path.into_inner())
+        .execute(&data.pool).await
+        .map_err(|e| AppError::Internal(e.to_string()))?;
+    if res.rows_affected() == 0 {
+        return Err(AppError::NotFound);
+    }
+    Ok(HttpResponse::NoContent().finish())
 }
 
 #[derive(Deserialize)]
@@ -74,8 +841,15 @@ async fn login(body: web::Json<LoginBody>, data: web::Data<AppState>) -> Result<
     if body.username.trim().is_empty() || body.password.trim().is_empty() {
         return Err(AppError::Unauthorized);
     }
-    let exp = (Utc::now() + chrono::Duration::hours(12)).timestamp() as usize;
-    let claims = Claims { sub: body.username.clone(), exp };
+    let now = Utc::now();
+    let exp = (now + chrono::Duration::hours(12)).timestamp() as usize;
+    let claims = Claims {
+        sub: body.username.clone(),
+        exp,
+        iat: now.timestamp() as usize,
+        iss: data.jwt_issuer.clone(),
+        aud: data.jwt_audience.clone(),
+    };
     let token = encode(&Header::default(), &claims, &EncodingKey::from_secret(data.jwt_secret.as_ref().unwrap().as_bytes()))
         .map_err(|_| AppError::Internal("Failed to sign token".into()))?;
     Ok(HttpResponse::Ok().json(serde_json::json!({
@@ -94,28 +868,186 @@ enum AppError {
     NotFound,
     #[error("Unauthorized")]
     Unauthorized,
+    #[error("Forbidden")]
+    Forbidden,
+    #[error("Conflict: {0}")]
+    Conflict(String),
+    /// A well-formed body that fails field rules (422). Unparseable bodies stay 400, see `json_error`.
+    #[error("Unprocessable Entity: {0}")]
+    Validation(ValidationErrors),
+    #[error("Unsupported Media Type: {0}")]
+    UnsupportedMediaType(String),
     #[error("Internal Server Error: {0}")]
     Internal(String),
 }
 
+/// Single-field `Validation` error for checks that aren't `#[validate]` attributes.
+fn field_error(field: &'static str, code: &'static str, message: impl Into<String>) -> AppError {
+    let mut errors = ValidationErrors::new();
+    errors.add(field, ValidationError::new(code).with_message(message.into().into()));
+    AppError::Validation(errors)
+}
+
+/// Deserializes a request body into `T`. With `STRICT_JSON` on, top-level fields `T` doesn't declare
+/// (per its JSON Schema) are rejected with 400 instead of being ignored.
+fn parse_body<T: DeserializeOwned + JsonSchema>(body: serde_json::Value, data: &AppState) -> Result<T, AppError> {
+    if data.strict_json {
+        if let (Some(fields), Some(known)) = (body.as_object(), schema_for!(T).schema.object) {
+            let unknown: Vec<&str> = fields.keys().filter(|k| !known.properties.contains_key(*k)).map(String::as_str).collect();
+            if !unknown.is_empty() {
+                return Err(AppError::BadRequest(format!(
+                    "unknown fields: {} (expected {})",
+                    unknown.join(", "),
+                    known.properties.keys().cloned().collect::<Vec<_>>().join(", "),
+                )));
+            }
+        }
+    }
+    serde_json::from_value(body).map_err(|e| AppError::BadRequest(format!("Json deserialize error: {}", e)))
+}
+
+/// Body extraction failures (malformed JSON, wrong types, too large, wrong content type) keep
+/// actix's status but get the same `{"error": ...}` body as `AppError`.
+fn json_error(err: actix_web::error::JsonPayloadError, _req: &HttpRequest) -> actix_web::Error {
+    let status = actix_web::ResponseError::status_code(&err);
+    let response = HttpResponse::build(status).json(serde_json::json!({ "error": err.to_string() }));
+    actix_web::error::InternalError::from_response(err, response).into()
+}
+
+/// The `fields` object of a 422 body: each failing field with its messages (or codes).
+fn field_messages(errors: &ValidationErrors) -> HashMap<String, Vec<String>> {
+    errors.field_errors().into_iter()
+        .map(|(field, errs)| (
+            field.to_string(),
+            errs.iter().map(|e| e.message.as_ref().map(|m| m.to_string()).unwrap_or_else(|| e.code.to_string())).collect(),
+        ))
+        .collect()
+}
+
 impl actix_web::ResponseError for AppError {
     fn error_response(&self) -> HttpResponse {
         match self {
             AppError::BadRequest(msg) => HttpResponse::BadRequest().json(serde_json::json!({ "error": msg })),
             AppError::NotFound => HttpResponse::NotFound().json(serde_json::json!({ "error": "Not Found" })),
             AppError::Unauthorized => HttpResponse::Unauthorized().json(serde_json::json!({ "error": "Unauthorized" })),
+            AppError::Forbidden => HttpResponse::Forbidden().json(serde_json::json!({ "error": "Forbidden" })),
+            AppError::Conflict(msg) => HttpResponse::Conflict().json(serde_json::json!({ "error": msg })),
+            AppError::Validation(errors) => HttpResponse::UnprocessableEntity()
+                .json(serde_json::json!({ "error": errors.to_string(), "fields": field_messages(errors) })),
+            AppError::UnsupportedMediaType(msg) => HttpResponse::UnsupportedMediaType().json(serde_json::json!({ "error": msg })),
             AppError::Internal(msg) => HttpResponse::InternalServerError().json(serde_json::json!({ "error": msg })),
         }
     }
 }
 
-// ---------- State ----------
+// ---------- Encryption ----------
 
-struct AppState {
-    pool: SqlitePool,
+/// Prefix marking an encrypted column value: `enc:v1:` + base64(nonce || ciphertext).
+/// Values without it are plaintext (rows written before `ENCRYPTION_KEY` was set).
+const ENCRYPTED_PREFIX: &str = "enc:v1:";
+
+fn encrypt_field(data: &AppState, plaintext: &str) -> Result<String, AppError> {
+    let Some(cipher) = &data.cipher else {
+        return Ok(plaintext.to_string());
+    };
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let ciphertext = cipher.encrypt(&nonce, plaintext.as_bytes())
+        .map_err(|_| AppError::Internal("Failed to encrypt field".into()))?;
+    let mut blob = nonce.to_vec();
+    blob.extend_from_slice(&ciphertext);
+    Ok(format!("{}{}", ENCRYPTED_PREFIX, BASE64.encode(blob)))
+}
+
+fn decrypt_field(data: &AppState, stored: &str) -> Result<String, AppError> {
+    let Some(encoded) = stored.strip_prefix(ENCRYPTED_PREFIX) else {
+        return Ok(stored.to_string());
+    };
+    let cipher = data.cipher.as_ref()
+        .ok_or_else(|| AppError::Internal("Encrypted data found but ENCRYPTION_KEY is not set".into()))?;
+    let blob = BASE64.decode(encoded)
+        .map_err(|_| AppError::Internal("Corrupt encrypted field".into()))?;
+    if blob.len() < 12 {
+        return Err(AppError::Internal("Corrupt encrypted field".into()));
+    }
+    let (nonce, ciphertext) = blob.split_at(12);
+    let plaintext = cipher.decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| AppError::Internal("Failed to decrypt field (wrong ENCRYPTION_KEY?)".into()))?;
+    String::from_utf8(plaintext).map_err(|_| AppError::Internal("Corrupt encrypted field".into()))
+}
+
+// ---------- State ----------
+
+struct AppState {
+    pool: SqlitePool,
     jwt_enabled: bool,
     jwt_secret: Option<String>,
+    /// Header and prefix the JWT is read from (`AUTH_HEADER`, `AUTH_SCHEME`).
+    auth_header: HeaderName,
+    auth_scheme: String,
+    /// `STRICT_JSON`: reject unknown fields in task create/update bodies.
+    strict_json: bool,
+    max_json_bytes: usize,
+    /// `MAX_RESTORE_BYTES`: body limit for `POST /api/admin/restore` instead of `max_json_bytes`.
+    max_restore_bytes: usize,
+    trim_trailing_slash: bool,
+    jwt_issuer: Option<String>,
+    jwt_audience: Option<String>,
+    anonymous_subject: String,
+    /// `ALLOW_RESET`: enables `POST /api/admin/reset`.
+    allow_reset: bool,
     read_only_without_jwt: bool,
+    changes: broadcast::Sender<i64>,
+    poll_timeout: Duration,
+    cipher: Option<Aes256Gcm>,
+    max_attachments_per_task: i64,
+    admin_users: Vec<String>,
+    /// `USER_EDITABLE_FIELDS`; `None` lets everyone edit every field.
+    user_editable_fields: Option<Vec<&'static str>>,
+    vacuum_in_progress: AtomicBool,
+    server_timing: bool,
+    max_title_len: usize,
+    global_rate_limiter: Option<RateLimiter>,
+    /// `MAX_CONCURRENT_REQUESTS` permits; `None` is unlimited.
+    request_semaphore: Option<Arc<Semaphore>>,
+    /// Per-IP limit for the unauthenticated `/api/token/introspect`.
+    introspect_rate_limiter: RateLimiter,
+    rate_limit_mode: RateLimitMode,
+    default_list_completed: CompletedFilter,
+    default_sort: ListSort,
+    title_control_chars: ControlCharMode,
+    /// `SOFT_VALIDATION`: entries of `SOFT_RULES` that only warn on create.
+    soft_validation: Vec<&'static str>,
+    security_headers: SecurityHeaders,
+    write_behind: Option<mpsc::Sender<WriteBehindMsg>>,
+    lock_completed_tasks: bool,
+    slow_query: Option<Duration>,
+    list_cache: Option<ListCache>,
+    /// `DEDUP_WINDOW_MS`; `None` disables double-submit dedup.
+    recent_creates: Option<RecentCreates>,
+    envelope_responses: bool,
+    write_behind_seq: AtomicU64,
+    started_at: i64,
+}
+
+// ---------- Transactions ----------
+
+/// Runs `f` in a transaction: committed if it returns `Ok`, rolled back on `Err`, so a multi-step
+/// mutation never partially applies. `f` must own what it captures (clone `req`/`data` in).
+async fn in_transaction<T, F>(pool: &SqlitePool, f: F) -> Result<T, AppError>
+where
+    F: for<'c> FnOnce(&'c mut SqliteConnection) -> LocalBoxFuture<'c, Result<T, AppError>>,
+{
+    let mut tx = pool.begin().await.map_err(|e| AppError::Internal(e.to_string()))?;
+    match f(&mut tx).await {
+        Ok(value) => {
+            tx.commit().await.map_err(|e| AppError::Internal(e.to_string()))?;
+            Ok(value)
+        }
+        Err(e) => {
+            tx.rollback().await.map_err(|e| AppError::Internal(e.to_string()))?;
+            Err(e)
+        }
+    }
 }
 
 // ---------- Handlers ----------
@@ -123,166 +1055,4016 @@ struct AppState {
 #[post("/api/tasks")]
 async fn create_task(
     req: HttpRequest,
+    query: web::Query<CreateTaskQuery>,
     data: web::Data<AppState>,
-    payload: web::Json<CreateTask>,
+    body: web::Json<serde_json::Value>,
 ) -> Result<impl Responder, AppError> {
-    ensure_auth(&req, &data).await?;
-    payload.validate().map_err(|e| AppError::BadRequest(e.to_string()))?;
+    let actor = ensure_auth(&req, &data).await?;
+    let payload: CreateTask = parse_body(body.into_inner(), &data)?;
+    payload.validate().map_err(AppError::Validation)?;
+    let mut warnings = Vec::new();
+    let title = clean_title_with_warnings(&data, &payload.title, Some(&mut warnings))?;
+
+    let (_, progress) = resolve_progress(false, 0, None, payload.progress)?;
+    let expires_at = payload.ttl_seconds.map(expires_at_from_ttl).transpose()?;
+
+    if let Some(id) = data.recent_creates.as_ref().and_then(|recent| recent.get(&actor, &title)) {
+        let rec = db_timed(&req, sqlx::query(&format!("SELECT {} FROM tasks WHERE id = ?", TASK_COLUMNS))
+            .bind(id)
+            .fetch_optional(&data.pool)).await
+            .map_err(|e| AppError::Internal(e.to_string()))?;
+        // A task deleted in the meantime is simply created again.
+        if let Some(rec) = rec {
+            return Ok(HttpResponse::Ok().json(Task::from_row(&rec, &data)?));
+        }
+    }
+
+    if query.unique_title.unwrap_or(false) {
+        return create_task_if_title_absent(&req, &data, &actor, &title, progress, payload.estimate_minutes, expires_at, warnings).await;
+    }
+
+    if let Some(queue) = &data.write_behind {
+        let provisional_id = format!("wb-{}-{}", data.started_at, data.write_behind_seq.fetch_add(1, Ordering::Relaxed));
+        queue.send(WriteBehindMsg::Task(PendingTask {
+            title,
+            progress,
+            estimate_minutes: payload.estimate_minutes,
+            expires_at,
+            actor,
+            provisional_id: provisional_id.clone(),
+        })).await
+            .map_err(|_| AppError::Internal("write-behind queue is closed".into()))?;
+        let mut body = serde_json::json!({
+            "provisional_id": provisional_id,
+            "status": "queued",
+        });
+        if !warnings.is_empty() {
+            body["warnings"] = serde_json::json!(warnings);
+        }
+        return Ok(HttpResponse::Accepted().json(body));
+    }
+
+    let estimate_minutes = payload.estimate_minutes;
+    let (task, change_id) = in_transaction(&data.pool, {
+        let (req, data, actor, title) = (req.clone(), data.clone(), actor.clone(), title.clone());
+        move |conn| Box::pin(async move {
+            let task = insert_task(&req, conn, &data, &actor, &title, progress, estimate_minutes, expires_at).await?;
+            let change_id = db_timed(&req, append_change(conn, task.id, "created")).await?;
+            Ok((task, change_id))
+        })
+    }).await?;
+
+    notify_change(&data, change_id);
+    if let Some(recent) = &data.recent_creates {
+        recent.put(&actor, &title, task.id);
+    }
+    Ok(HttpResponse::Created().json(CreatedTask { task, warnings }))
+}
+
+/// A newly created task plus any `SOFT_VALIDATION` warnings raised while creating it.
+#[derive(Serialize)]
+struct CreatedTask {
+    #[serde(flatten)]
+    task: Task,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    warnings: Vec<String>,
+}
+
+/// `POST /api/tasks?unique_title=true`: 201 with the new task, or 409 pointing at the caller's existing
+/// task with the same title. The task is inserted first and the lookup runs after it in the same
+/// transaction: the insert takes SQLite's write lock, so a concurrent request waits for it and then sees
+/// its row, instead of both reading "absent" and one failing with SQLITE_BUSY.
+#[allow(clippy::too_many_arguments)]
+async fn create_task_if_title_absent(
+    req: &HttpRequest,
+    data: &AppState,
+    actor: &str,
+    title: &str,
+    progress: i64,
+    estimate_minutes: Option<i64>,
+    expires_at: Option<String>,
+    warnings: Vec<String>,
+) -> Result<HttpResponse, AppError> {
+    let mut tx = data.pool.begin().await.map_err(|e| AppError::Internal(e.to_string()))?;
+    // With ENFORCE_UNIQUE_TITLES the index rejects the insert; the lookup below still finds the id.
+    let inserted = match insert_task(req, &mut tx, data, actor, title, progress, estimate_minutes, expires_at).await {
+        Err(e) if !matches!(e, AppError::Conflict(_)) => return Err(e),
+        inserted => inserted,
+    };
+    let own_id = inserted.as_ref().map_or(0, |task| task.id);
+    let existing = if data.cipher.is_some() {
+        // Encrypted titles can't be compared in SQL, so decrypt the caller's tasks.
+        let rows = db_timed(req, sqlx::query("SELECT id, title FROM tasks WHERE created_by = ? AND id != ? ORDER BY id")
+            .bind(actor)
+            .bind(own_id)
+            .fetch_all(&mut *tx)).await
+            .map_err(|e| AppError::Internal(e.to_string()))?;
+        let mut found = None;
+        for row in &rows {
+            if decrypt_field(data, &row.get::<String, _>("title"))? == title {
+                found = Some(row.get::<i64, _>("id"));
+                break;
+            }
+        }
+        found
+    } else {
+        db_timed(req, sqlx::query_scalar("SELECT id FROM tasks WHERE created_by = ? AND title = ? AND id != ? ORDER BY id LIMIT 1")
+            .bind(actor)
+            .bind(title)
+            .bind(own_id)
+            .fetch_optional(&mut *tx)).await
+            .map_err(|e| AppError::Internal(e.to_string()))?
+    };
+    if let Some(id) = existing {
+        tx.rollback().await.map_err(|e| AppError::Internal(e.to_string()))?;
+        return Ok(HttpResponse::Conflict()
+            .insert_header((LOCATION, format!("/api/tasks/{}", id)))
+            .json(serde_json::json!({ "error": "a task with this title already exists", "id": TaskId(id) })));
+    }
+    let task = inserted?;
+    let change_id = db_timed(req, append_change(&mut tx, task.id, "created")).await?;
+    tx.commit().await.map_err(|e| AppError::Internal(e.to_string()))?;
+
+    notify_change(data, change_id);
+    if let Some(recent) = &data.recent_creates {
+        recent.put(actor, title, task.id);
+    }
+    Ok(HttpResponse::Created()
+        .insert_header((LOCATION, format!("/api/tasks/{}", task.id)))
+        .json(CreatedTask { task, warnings }))
+}
+
+/// Inserts a new task at the end of the list; `completed` follows from `progress`.
+#[allow(clippy::too_many_arguments)]
+async fn insert_task(
+    req: &HttpRequest,
+    conn: &mut SqliteConnection,
+    data: &AppState,
+    actor: &str,
+    title: &str,
+    progress: i64,
+    estimate_minutes: Option<i64>,
+    expires_at: Option<String>,
+) -> Result<Task, AppError> {
+    let rec = db_timed(req, sqlx::query(&format!(
+        "INSERT INTO tasks (title, completed, progress, estimate_minutes, expires_at, created_by, updated_by, position) \
+         VALUES (?, ?, ?, ?, ?, ?, ?, {}) RETURNING {}",
+        next_position_sql(), TASK_COLUMNS
+    ))
+    .bind(encrypt_field(data, title)?)
+    .bind(progress == 100)
+    .bind(progress)
+    .bind(estimate_minutes)
+    .bind(expires_at)
+    .bind(actor)
+    .bind(actor)
+    .fetch_one(conn)).await
+    .map_err(task_write_error)?;
+    Task::from_row(&rec, data)
+}
+
+/// A bare task id, honouring `STRING_IDS`.
+#[derive(Serialize)]
+struct TaskId(#[serde(serialize_with = "serialize_id")] i64);
+
+fn list_body<T: Serialize>(items: &[T], envelope: bool) -> Result<String, AppError> {
+    if envelope {
+        serde_json::to_string(&serde_json::json!({ "items": items, "total": items.len() }))
+    } else {
+        serde_json::to_string(items)
+    }.map_err(|e| AppError::Internal(e.to_string()))
+}
+
+/// The `return=` preference of an RFC 7240 `Prefer` header, if the request states one:
+/// `Some(true)` for `return=minimal`, `Some(false)` for `return=representation`.
+fn prefer_minimal(req: &HttpRequest) -> Option<bool> {
+    req.headers().get_all("prefer")
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .filter_map(|pref| pref.split(';').next())
+        .find_map(|pref| match pref.trim().to_lowercase().replace(' ', "").as_str() {
+            "return=minimal" => Some(true),
+            "return=representation" => Some(false),
+            _ => None,
+        })
+}
+
+/// With `Prefer: return=minimal` only task ids are returned (in the same order and envelope);
+/// `return=representation` or no preference returns full tasks.
+/// Responses carry a weak `ETag` built from the newest change id and the size of the filtered set, so
+/// it changes with any write (including deletions) and with snoozes running out. It is checked before
+/// the list query runs, so a matching `If-None-Match` gets a 304 without reading the page.
+#[get("/api/tasks")]
+async fn list_tasks(
+    req: HttpRequest,
+    query: web::Query<ListQuery>,
+    data: web::Data<AppState>,
+) -> Result<impl Responder, AppError> {
+    if let Some(since) = query.modified_since {
+        return list_tasks_modified_since(&req, &query, &data, since).await;
+    }
+
+    let minimal = prefer_minimal(&req);
+    let etag = list_etag(&req, &query, &data, minimal == Some(true)).await?;
+    let not_modified = if_none_match(&req, &etag);
+    let mut response = if not_modified { HttpResponse::NotModified() } else { HttpResponse::Ok() };
+    response.insert_header(("ETag", etag.as_str())).insert_header(("Vary", "Prefer"));
+    if let Some(m) = minimal {
+        response.insert_header(("Preference-Applied", if m { "return=minimal" } else { "return=representation" }));
+    }
+    if not_modified {
+        return Ok(response.finish());
+    }
+
+    let cache_key = format!("{}|{}|{}", request_subject(&req, &data).await, req.query_string(), minimal.unwrap_or(false));
+    let (body, cache_status) = match data.list_cache.as_ref().map(|cache| (cache.get(&cache_key), cache)) {
+        Some((Some(body), _)) => (body, Some("HIT")),
+        cached => {
+            let generation = cached.map(|(_, cache)| cache.generation());
+            let body = fetch_list_body(&req, &query, &data, minimal == Some(true)).await?;
+            if let (Some(cache), Some(generation)) = (&data.list_cache, generation) {
+                cache.put(cache_key, body.clone(), generation);
+            }
+            (body, generation.map(|_| "MISS"))
+        }
+    };
+
+    if let Some(status) = cache_status {
+        response.insert_header(("X-Cache", status));
+    }
+    Ok(response.content_type("application/json").body(body))
+}
+
+/// The list's `ETag`, from one aggregate query: every write appends to `task_changes`, and the count
+/// of the filtered set moves when a snooze runs out or a time-relative filter rolls over.
+async fn list_etag(req: &HttpRequest, query: &ListQuery, data: &AppState, minimal: bool) -> Result<String, AppError> {
+    let mut qb = QueryBuilder::new(
+        "SELECT COUNT(*) AS task_count, (SELECT COALESCE(MAX(id), 0) FROM task_changes) AS last_change FROM tasks"
+    );
+    push_list_filters(&mut qb, query, data)?;
+    let row = db_timed(req, qb.build().fetch_one(&data.pool)).await
+        .map_err(|e| AppError::Internal(e.to_string()))?;
+    let validator = format!(
+        "{}|{}|{}|{}",
+        req.query_string(), minimal, row.get::<i64, _>("last_change"), row.get::<i64, _>("task_count")
+    );
+    let digest: String = Sha256::digest(validator.as_bytes()).iter().take(16).map(|b| format!("{:02x}", b)).collect();
+    Ok(format!("W/\"{}\"", digest))
+}
+
+/// Runs the list query and serializes it as `list_tasks` returns it (ids only when `minimal`).
+async fn fetch_list_body(req: &HttpRequest, query: &ListQuery, data: &AppState, minimal: bool) -> Result<String, AppError> {
+    let tz = parse_tz(query.tz.as_deref())?;
+    let mut qb = QueryBuilder::new(format!("SELECT {} FROM tasks", TASK_COLUMNS));
+    push_list_filters(&mut qb, query, data)?;
+    push_list_order(&mut qb, query, data)?;
+    let rows = db_timed(req, qb.build().fetch_all(&data.pool)).await
+        .map_err(|e| AppError::Internal(e.to_string()))?;
+    let tasks = rows.iter()
+        .map(|rec| Task::from_row(rec, data).map(|t| t.in_timezone(tz)))
+        .collect::<Result<Vec<_>, _>>()?;
+    let envelope = query.envelope.unwrap_or(data.envelope_responses);
+    if minimal {
+        list_body(&tasks.iter().map(|t| TaskId(t.id)).collect::<Vec<_>>(), envelope)
+    } else {
+        list_body(&tasks, envelope)
+    }
+}
+
+/// Whether `If-None-Match` lists `etag` (or `*`), using weak comparison.
+fn if_none_match(req: &HttpRequest, etag: &str) -> bool {
+    let bare = |tag: &str| tag.trim().trim_start_matches("W/").to_string();
+    req.headers().get_all("if-none-match")
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .any(|tag| tag.trim() == "*" || bare(tag) == bare(etag))
+}
+
+/// Delta sync built on the `task_changes` log, since tasks have no `updated_at`: tasks created or
+/// changed at or after `since` (other list filters still apply), plus ids deleted since then.
+/// `changed_at` has one-second resolution, so the boundary second is included again; clients pass
+/// `server_time` back as the next `modified_since` and may see a few rows twice, never miss one.
+async fn list_tasks_modified_since(
+    req: &HttpRequest,
+    query: &ListQuery,
+    data: &AppState,
+    since: DateTime<Utc>,
+) -> Result<HttpResponse, AppError> {
+    let tz = parse_tz(query.tz.as_deref())?;
+    let since = since.format("%Y-%m-%d %H:%M:%S").to_string();
+    // Read before the queries, so nothing committed while they run falls between two syncs.
+    let server_time: String = sqlx::query_scalar("SELECT strftime('%Y-%m-%dT%H:%M:%SZ', 'now')")
+        .fetch_one(&data.pool).await
+        .map_err(|e| AppError::Internal(e.to_string()))?;
 
-    let rec = sqlx::query(
-        "INSERT INTO tasks (title, completed) VALUES (?, ?) RETURNING id, title, completed, created_at"
+    let mut qb = QueryBuilder::new(format!("SELECT {} FROM tasks", TASK_COLUMNS));
+    push_list_filters(&mut qb, query, data)?;
+    qb.push(" AND id IN (SELECT task_id FROM task_changes WHERE changed_at >= ").push_bind(since.clone()).push(")");
+    push_list_order(&mut qb, query, data)?;
+    let rows = db_timed(req, qb.build().fetch_all(&data.pool)).await
+        .map_err(|e| AppError::Internal(e.to_string()))?;
+    let tasks = rows.iter()
+        .map(|rec| Task::from_row(rec, data).map(|t| t.in_timezone(tz)))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let deleted: Vec<i64> = db_timed(req, sqlx::query_scalar(
+        "SELECT DISTINCT task_id FROM task_changes WHERE op = 'deleted' AND changed_at >= ? \
+         AND task_id NOT IN (SELECT id FROM tasks) ORDER BY task_id"
     )
-    .bind(&payload.title)
-    .bind(false)
-    .fetch_one(&data.pool).await
+    .bind(&since)
+    .fetch_all(&data.pool)).await
     .map_err(|e| AppError::Internal(e.to_string()))?;
 
-    let task = Task {
-        id: rec.get::<i64, _>("id"),
-        title: rec.get::<String, _>("title"),
-        completed: rec.get::<i64, _>("completed") != 0,
-        created_at: rec.get::<String, _>("created_at"),
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "items": tasks,
+        "deleted": deleted.into_iter().map(TaskId).collect::<Vec<_>>(),
+        "server_time": server_time,
+    })))
+}
+
+/// Same filters and order as `list_tasks`, written as one JSON object per line while rows are read,
+/// so memory stays flat however many tasks there are. A bounded channel applies backpressure from a
+/// slow client to the query.
+#[get("/api/tasks/stream.ndjson")]
+async fn stream_tasks(query: web::Query<ListQuery>, data: web::Data<AppState>) -> Result<impl Responder, AppError> {
+    let tz = parse_tz(query.tz.as_deref())?;
+    let mut qb: QueryBuilder<'static, Sqlite> = QueryBuilder::new(format!("SELECT {} FROM tasks", TASK_COLUMNS));
+    push_list_filters(&mut qb, &query, &data)?;
+    push_list_order(&mut qb, &query, &data)?;
+
+    let (tx, rx) = mpsc::channel::<Result<web::Bytes, AppError>>(64);
+    let data = data.clone();
+    tokio::spawn(async move {
+        let mut rows = qb.build().fetch(&data.pool);
+        while let Some(row) = rows.next().await {
+            let line = row
+                .map_err(|e| AppError::Internal(e.to_string()))
+                .and_then(|rec| Task::from_row(&rec, &data).map(|t| t.in_timezone(tz)))
+                .and_then(|task| serde_json::to_vec(&task).map_err(|e| AppError::Internal(e.to_string())))
+                .map(|mut line| {
+                    line.push(b'\n');
+                    web::Bytes::from(line)
+                });
+            let failed = line.is_err();
+            // Stop reading once the client has gone away or after reporting an error.
+            if tx.send(line).await.is_err() || failed {
+                break;
+            }
+        }
+    });
+
+    let body = futures_util::stream::unfold(rx, |mut rx| async move { rx.recv().await.map(|item| (item, rx)) });
+    Ok(HttpResponse::Ok().content_type("application/x-ndjson").streaming(body))
+}
+
+/// `{"count": n}` for the tasks `GET /api/tasks` would return with the same filters (including
+/// `modified_since`), without fetching them. Sort and presentation params are ignored.
+#[get("/api/tasks/count")]
+async fn count_tasks(
+    req: HttpRequest,
+    query: web::Query<ListQuery>,
+    data: web::Data<AppState>,
+) -> Result<impl Responder, AppError> {
+    let mut qb = QueryBuilder::new("SELECT COUNT(*) FROM tasks");
+    push_list_filters(&mut qb, &query, &data)?;
+    if let Some(since) = query.modified_since {
+        let since = since.format("%Y-%m-%d %H:%M:%S").to_string();
+        qb.push(" AND id IN (SELECT task_id FROM task_changes WHERE changed_at >= ").push_bind(since).push(")");
+    }
+    let count: i64 = db_timed(&req, qb.build_query_scalar().fetch_one(&data.pool)).await
+        .map_err(|e| AppError::Internal(e.to_string()))?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "count": count })))
+}
+
+#[derive(Deserialize)]
+struct WorkloadQuery {
+    group_by: Option<String>,
+}
+
+/// Sum of `estimate_minutes` over incomplete tasks (unestimated tasks count as 0). Always 200,
+/// with zeros when there is nothing to do.
+#[get("/api/tasks/workload")]
+async fn task_workload(
+    req: HttpRequest,
+    query: web::Query<WorkloadQuery>,
+    data: web::Data<AppState>,
+) -> Result<impl Responder, AppError> {
+    const TOTALS: &str = "COALESCE(SUM(estimate_minutes), 0) AS total_minutes, COUNT(*) AS task_count, \
+                          COUNT(estimate_minutes) AS estimated_count";
+    let totals = |r: &SqliteRow| serde_json::json!({
+        "total_minutes": r.get::<i64, _>("total_minutes"),
+        "task_count": r.get::<i64, _>("task_count"),
+        "estimated_count": r.get::<i64, _>("estimated_count"),
+    });
+    match query.group_by.as_deref() {
+        None => {
+            let row = db_timed(&req, sqlx::query(&format!("SELECT {} FROM tasks WHERE completed = 0", TOTALS))
+                .fetch_one(&data.pool)).await
+                .map_err(|e| AppError::Internal(e.to_string()))?;
+            Ok(HttpResponse::Ok().json(totals(&row)))
+        }
+        Some("created_by") => {
+            let rows = db_timed(&req, sqlx::query(&format!(
+                "SELECT created_by, {} FROM tasks WHERE completed = 0 GROUP BY created_by ORDER BY total_minutes DESC, created_by ASC",
+                TOTALS
+            ))
+            .fetch_all(&data.pool)).await
+            .map_err(|e| AppError::Internal(e.to_string()))?;
+            let groups: Vec<serde_json::Value> = rows.iter().map(|r| {
+                let mut group = totals(r);
+                group["created_by"] = serde_json::json!(r.get::<String, _>("created_by"));
+                group
+            }).collect();
+            Ok(HttpResponse::Ok().json(serde_json::json!({ "group_by": "created_by", "groups": groups })))
+        }
+        Some(other) => Err(AppError::BadRequest(format!("invalid group_by '{}' (expected created_by)", other))),
+    }
+}
+
+#[derive(Deserialize)]
+struct ActivityQuery {
+    /// `created` (default) or `completed`.
+    metric: Option<String>,
+    days: Option<i64>,
+    tz: Option<String>,
+}
+
+const MAX_ACTIVITY_DAYS: i64 = 366;
+
+/// Per-day counts for the last `days` days including today, `{"2026-01-01": 3, ...}`, with a zero for
+/// every day without activity. Days are local to `?tz=` (UTC if omitted); each day's UTC range is
+/// bound into the query, so DST changes shift the boundaries correctly.
+#[get("/api/tasks/activity")]
+async fn task_activity(
+    req: HttpRequest,
+    query: web::Query<ActivityQuery>,
+    data: web::Data<AppState>,
+) -> Result<impl Responder, AppError> {
+    let column = match query.metric.as_deref().unwrap_or("created") {
+        "created" => "created_at",
+        "completed" => "completed_at",
+        other => return Err(AppError::BadRequest(format!("invalid metric '{}' (expected created or completed)", other))),
     };
-    Ok(HttpResponse::Created().json(task))
+    let days = query.days.unwrap_or(30);
+    if days < 1 {
+        return Err(AppError::BadRequest("days must be at least 1".into()));
+    }
+    let days = days.min(MAX_ACTIVITY_DAYS);
+    let tz = parse_tz(query.tz.as_deref())?.unwrap_or(Tz::UTC);
+    let today = Utc::now().with_timezone(&tz).date_naive();
+
+    let mut qb = QueryBuilder::new("WITH days(day, start_at, end_at) AS (VALUES ");
+    for i in 0..days {
+        let day = today - chrono::Duration::days(days - 1 - i);
+        if i > 0 {
+            qb.push(", ");
+        }
+        qb.push("(").push_bind(day.format("%Y-%m-%d").to_string())
+            .push(", ").push_bind(local_midnight_utc(day, tz))
+            .push(", ").push_bind(local_midnight_utc(day + chrono::Duration::days(1), tz))
+            .push(")");
+    }
+    qb.push(format_args!(
+        ") SELECT d.day, COUNT(t.id) AS n FROM days d \
+         LEFT JOIN tasks t ON t.{0} >= d.start_at AND t.{0} < d.end_at GROUP BY d.day ORDER BY d.day",
+        column
+    ));
+    let rows = db_timed(&req, qb.build().fetch_all(&data.pool)).await
+        .map_err(|e| AppError::Internal(e.to_string()))?;
+    let buckets: serde_json::Map<String, serde_json::Value> = rows.iter()
+        .map(|r| (r.get::<String, _>("day"), r.get::<i64, _>("n").into()))
+        .collect();
+    Ok(HttpResponse::Ok().json(buckets))
 }
 
-#[get("/api/tasks")]
-async fn list_tasks(data: web::Data<AppState>) -> Result<impl Responder, AppError> {
-    let rows = sqlx::query("SELECT id, title, completed, created_at FROM tasks ORDER BY id DESC")
-        .fetch_all(&data.pool).await
+#[derive(Deserialize)]
+struct GroupedQuery {
+    by: Option<String>,
+}
+
+/// Tasks bucketed for board views, `{"todo":[...],"done":[...]}` for `by=status`. The list filters
+/// and sort apply inside each group, and every group is present even when empty.
+#[get("/api/tasks/grouped")]
+async fn grouped_tasks(
+    req: HttpRequest,
+    grouped: web::Query<GroupedQuery>,
+    query: web::Query<ListQuery>,
+    data: web::Data<AppState>,
+) -> Result<impl Responder, AppError> {
+    match grouped.by.as_deref() {
+        Some("status") => {}
+        Some(field @ ("priority" | "tag")) => {
+            return Err(AppError::BadRequest(format!("tasks have no {} yet; only by=status is supported", field)));
+        }
+        Some(other) => return Err(AppError::BadRequest(format!("invalid by '{}' (expected status)", other))),
+        None => return Err(AppError::BadRequest("by is required (expected status)".into())),
+    }
+    let tz = parse_tz(query.tz.as_deref())?;
+    let mut qb = QueryBuilder::new(format!("SELECT {} FROM tasks", TASK_COLUMNS));
+    push_list_filters(&mut qb, &query, &data)?;
+    push_list_order(&mut qb, &query, &data)?;
+    let rows = db_timed(&req, qb.build().fetch_all(&data.pool)).await
         .map_err(|e| AppError::Internal(e.to_string()))?;
-    let tasks: Vec<Task> = rows.into_iter().map(|rec| Task {
-        id: rec.get::<i64, _>("id"),
-        title: rec.get::<String, _>("title"),
-        completed: rec.get::<i64, _>("completed") != 0,
-        created_at: rec.get::<String, _>("created_at"),
-    }).collect();
-    Ok(HttpResponse::Ok().json(tasks))
+    let (mut todo, mut done) = (Vec::new(), Vec::new());
+    for rec in &rows {
+        let task = Task::from_row(rec, &data)?.in_timezone(tz);
+        if task.completed { done.push(task) } else { todo.push(task) }
+    }
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "todo": todo, "done": done })))
 }
 
 #[get("/api/tasks/{id}")]
-async fn get_task(path: web::Path<i64>, data: web::Data<AppState>) -> Result<impl Responder, AppError> {
+async fn get_task(
+    req: HttpRequest,
+    path: web::Path<i64>,
+    query: web::Query<GetTaskQuery>,
+    data: web::Data<AppState>,
+) -> Result<impl Responder, AppError> {
     let id = path.into_inner();
-    let rec = sqlx::query("SELECT id, title, completed, created_at FROM tasks WHERE id = ?")
+    let includes = Includes::parse(query.include.as_deref())?;
+    let tz = parse_tz(query.tz.as_deref())?;
+    let rec = db_timed(&req, sqlx::query(&format!("SELECT {} FROM tasks WHERE id = ?", TASK_COLUMNS))
         .bind(id)
-        .fetch_optional(&data.pool).await
+        .fetch_optional(&data.pool)).await
         .map_err(|e| AppError::Internal(e.to_string()))?;
 
-    if let Some(rec) = rec {
-        let task = Task {
-            id: rec.get::<i64, _>("id"),
-            title: rec.get::<String, _>("title"),
-            completed: rec.get::<i64, _>("completed") != 0,
-            created_at: rec.get::<String, _>("created_at"),
-        };
-        Ok(HttpResponse::Ok().json(task))
+    let Some(rec) = rec else {
+        return Err(AppError::NotFound);
+    };
+    let attachments = if includes.attachments {
+        Some(db_timed(&req, fetch_attachments(&data.pool, id)).await?)
     } else {
-        Err(AppError::NotFound)
-    }
+        None
+    };
+    let comment_count = if includes.comment_count {
+        Some(db_timed(&req, count_comments(&data.pool, id)).await?)
+    } else {
+        None
+    };
+    Ok(HttpResponse::Ok().json(TaskDetail { task: Task::from_row(&rec, &data)?.in_timezone(tz), attachments, comment_count }))
 }
 
 #[put("/api/tasks/{id}")]
 async fn update_task(
     req: HttpRequest,
     path: web::Path<i64>,
+    force: web::Query<ForceQuery>,
     data: web::Data<AppState>,
-    payload: web::Json<UpdateTask>,
+    body: web::Json<serde_json::Value>,
 ) -> Result<impl Responder, AppError> {
-    ensure_auth(&req, &data).await?;
-    payload.validate().map_err(|e| AppError::BadRequest(e.to_string()))?;
+    let actor = ensure_auth(&req, &data).await?;
+    let payload: UpdateTask = parse_body(body.into_inner(), &data)?;
+    payload.validate().map_err(AppError::Validation)?;
+    let title = payload.title.as_deref().map(|t| clean_title(&data, t)).transpose()?;
 
-    let id = path.into_inner();
+    let changes = TaskChanges {
+        title,
+        completed: payload.completed,
+        progress: payload.progress,
+        estimate_minutes: payload.estimate_minutes.map(Some),
+    };
+    let task = apply_task_update(&req, &data, actor, path.into_inner(), changes, force.force.unwrap_or(false)).await?;
+    Ok(HttpResponse::Ok().json(task))
+}
+
+#[derive(Deserialize)]
+struct PatchOp {
+    op: String,
+    path: String,
+    value: Option<serde_json::Value>,
+}
+
+/// Folds one RFC 6902 operation into the pending field changes. Patchable paths are `/title`,
+/// `/completed`, `/progress` and `/estimate_minutes`; only the estimate is optional and removable.
+fn apply_patch_op(changes: &mut TaskChanges, op: &PatchOp) -> Result<(), String> {
+    let value = || op.value.as_ref().ok_or_else(|| format!("'{}' requires a value", op.op));
+    match (op.op.as_str(), op.path.as_str()) {
+        ("replace" | "add", "/title") => {
+            let title = value()?.as_str().ok_or("/title must be a string")?;
+            changes.title = Some(title.to_string());
+        }
+        ("replace" | "add", "/completed") => {
+            changes.completed = Some(value()?.as_bool().ok_or("/completed must be a boolean")?);
+        }
+        ("replace" | "add", "/progress") => {
+            changes.progress = Some(value()?.as_i64().ok_or("/progress must be an integer")?);
+        }
+        ("replace" | "add", "/estimate_minutes") => {
+            changes.estimate_minutes = Some(Some(value()?.as_i64().ok_or("/estimate_minutes must be an integer")?));
+        }
+        ("remove", "/estimate_minutes") => changes.estimate_minutes = Some(None),
+        ("remove", "/title" | "/completed" | "/progress") => return Err(format!("{} is required and cannot be removed", op.path)),
+        ("replace" | "add" | "remove", other) => return Err(format!("unsupported path '{}'", other)),
+        (other, _) => return Err(format!("unsupported op '{}'", other)),
+    }
+    Ok(())
+}
+
+/// JSON Patch (RFC 6902) update. Operations are applied in order and validated together before
+/// anything is written; one bad operation rejects the whole patch.
+#[patch("/api/tasks/{id}")]
+async fn patch_task(
+    req: HttpRequest,
+    path: web::Path<i64>,
+    force: web::Query<ForceQuery>,
+    data: web::Data<AppState>,
+    ops: web::Json<Vec<PatchOp>>,
+) -> Result<impl Responder, AppError> {
+    let actor = ensure_auth(&req, &data).await?;
+    let is_json_patch = req.headers().get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.split(';').next().unwrap_or("").trim().eq_ignore_ascii_case("application/json-patch+json"))
+        .unwrap_or(false);
+    if !is_json_patch {
+        return Err(AppError::UnsupportedMediaType("PATCH requires Content-Type: application/json-patch+json".into()));
+    }
+    let mut changes = TaskChanges { title: None, completed: None, progress: None, estimate_minutes: None };
+    for (i, op) in ops.iter().enumerate() {
+        apply_patch_op(&mut changes, op).map_err(|e| AppError::BadRequest(format!("operation {}: {}", i, e)))?;
+    }
+    // Same rules as PUT
+    UpdateTask {
+        title: changes.title.clone(),
+        completed: changes.completed,
+        progress: changes.progress,
+        estimate_minutes: changes.estimate_minutes.flatten(),
+    }.validate().map_err(AppError::Validation)?;
+    changes.title = changes.title.as_deref().map(|t| clean_title(&data, t)).transpose()?;
+
+    let task = apply_task_update(&req, &data, actor, path.into_inner(), changes, force.force.unwrap_or(false)).await?;
+    Ok(HttpResponse::Ok().json(task))
+}
+
+/// With `LOCK_COMPLETED_TASKS`, no field of a completed task but `completed` itself can change (409),
+/// given the fields from `TaskChanges::modified_fields`. Reopening is always allowed, so a task can be
+/// reopened and then edited.
+fn check_not_locked(data: &AppState, current: &Task, modified: &[&str]) -> Result<(), AppError> {
+    if data.lock_completed_tasks && current.completed && modified.iter().any(|f| *f != "completed") {
+        return Err(AppError::Conflict("task is completed and locked; reopen it before editing".into()));
+    }
+    Ok(())
+}
+
+/// Shared by PUT and PATCH: writes the given fields (keeping the rest) and logs the change in one transaction.
+async fn apply_task_update(
+    req: &HttpRequest,
+    data: &web::Data<AppState>,
+    actor: String,
+    id: i64,
+    changes: TaskChanges,
+    force: bool,
+) -> Result<Task, AppError> {
+    let (task, change_id) = in_transaction(&data.pool, {
+        let (req, data) = (req.clone(), data.clone());
+        move |conn| Box::pin(async move { update_task_row(&req, conn, &data, &actor, id, changes, force).await })
+    }).await?;
+
+    notify_change(data, change_id);
+    Ok(task)
+}
+
+/// The body of `apply_task_update`, for callers that manage the transaction themselves.
+/// Returns the updated task and the id of its change-log entry.
+async fn update_task_row(
+    req: &HttpRequest,
+    conn: &mut SqliteConnection,
+    data: &AppState,
+    actor: &str,
+    id: i64,
+    changes: TaskChanges,
+    force: bool,
+) -> Result<(Task, i64), AppError> {
     // Fetch existing
-    let existing = sqlx::query("SELECT id, title, completed, created_at FROM tasks WHERE id = ?")
+    let existing = db_timed(req, sqlx::query(&format!("SELECT {} FROM tasks WHERE id = ?", TASK_COLUMNS))
         .bind(id)
-        .fetch_optional(&data.pool).await
+        .fetch_optional(&mut *conn)).await
         .map_err(|e| AppError::Internal(e.to_string()))?;
-    if existing.is_none() {
+    let Some(existing) = existing else {
         return Err(AppError::NotFound);
+    };
+    let current = Task::from_row(&existing, data)?;
+    let modified = changes.modified_fields(&current);
+    check_editable(data, actor, &modified)?;
+    check_not_locked(data, &current, &modified)?;
+    let new_title: String = changes.title.unwrap_or(current.title.clone());
+    let (new_completed, new_progress) =
+        resolve_progress(current.completed, current.progress, changes.completed, changes.progress)?;
+    let new_estimate = changes.estimate_minutes.unwrap_or(current.estimate_minutes);
+    if new_completed && !current.completed && !force {
+        db_timed(req, check_not_blocked(conn, id)).await?;
     }
-    let current = existing.unwrap();
-    let new_title: String = payload.title.clone().unwrap_or_else(|| current.get::<String, _>("title"));
-    let new_completed: bool = payload.completed.unwrap_or_else(|| current.get::<i64, _>("completed") != 0);
 
-    let rec = sqlx::query(
-        "UPDATE tasks SET title = ?, completed = ? WHERE id = ? RETURNING id, title, completed, created_at"
-    )
-    .bind(new_title)
+    let rec = db_timed(req, sqlx::query(&format!(
+        "UPDATE tasks SET title = ?, completed = ?, progress = ?, estimate_minutes = ?, updated_by = ? WHERE id = ? RETURNING {}",
+        TASK_COLUMNS
+    ))
+    .bind(encrypt_field(data, &new_title)?)
     .bind(new_completed)
+    .bind(new_progress)
+    .bind(new_estimate)
+    .bind(actor)
     .bind(id)
-    .fetch_one(&data.pool).await
-    .map_err(|e| AppError::Internal(e.to_string()))?;
+    .fetch_one(&mut *conn)).await
+    .map_err(task_write_error)?;
+    let change_id = db_timed(req, append_change(conn, id, "updated")).await?;
+    Ok((Task::from_row(&rec, data)?, change_id))
+}
 
-    let task = Task {
-        id: rec.get::<i64, _>("id"),
-        title: rec.get::<String, _>("title"),
-        completed: rec.get::<i64, _>("completed") != 0,
-        created_at: rec.get::<String, _>("created_at"),
+/// Create-or-update keyed by a client-generated id, so offline clients can retry pushes safely.
+/// `client_id` is unique per user (`created_by`); returns 201 when created, 200 when updated.
+#[put("/api/tasks/by-client-id/{client_id}")]
+async fn upsert_task_by_client_id(
+    req: HttpRequest,
+    path: web::Path<String>,
+    force: web::Query<ForceQuery>,
+    data: web::Data<AppState>,
+    payload: web::Json<UpsertTask>,
+) -> Result<impl Responder, AppError> {
+    let actor = ensure_auth(&req, &data).await?;
+    payload.validate().map_err(AppError::Validation)?;
+    let title = clean_title(&data, &payload.title)?;
+    let client_id = path.into_inner();
+    if client_id.trim().is_empty() || client_id.len() > 255 {
+        return Err(AppError::BadRequest("client_id must be 1-255 characters".into()));
+    }
+
+    let mut tx = data.pool.begin().await.map_err(|e| AppError::Internal(e.to_string()))?;
+    let existing = db_timed(&req, sqlx::query(&format!("SELECT {} FROM tasks WHERE created_by = ? AND client_id = ?", TASK_COLUMNS))
+        .bind(&actor)
+        .bind(&client_id)
+        .fetch_optional(&mut *tx)).await
+        .map_err(|e| AppError::Internal(e.to_string()))?;
+    let (completed, progress) = match &existing {
+        Some(existing) => {
+            let current = Task::from_row(existing, &data)?;
+            let changes = TaskChanges {
+                title: Some(title.clone()),
+                completed: payload.completed,
+                progress: payload.progress,
+                estimate_minutes: None,
+            };
+            let modified = changes.modified_fields(&current);
+            check_editable(&data, &actor, &modified)?;
+            check_not_locked(&data, &current, &modified)?;
+            let resolved = resolve_progress(current.completed, current.progress, payload.completed, payload.progress)?;
+            if resolved.0 && !current.completed && !force.force.unwrap_or(false) {
+                db_timed(&req, check_not_blocked(&mut tx, current.id)).await?;
+            }
+            resolved
+        }
+        None => resolve_progress(false, 0, payload.completed, payload.progress)?,
     };
-    Ok(HttpResponse::Ok().json(task))
+    let existed = existing.is_some();
+    let rec = db_timed(&req, sqlx::query(&format!(
+        "INSERT INTO tasks (title, completed, progress, created_by, updated_by, client_id, position) VALUES (?, ?, ?, ?, ?, ?, {}) \
+         ON CONFLICT(created_by, client_id) DO UPDATE SET \
+         title = excluded.title, completed = excluded.completed, progress = excluded.progress, updated_by = excluded.updated_by \
+         RETURNING {}",
+        next_position_sql(), TASK_COLUMNS
+    ))
+    .bind(encrypt_field(&data, &title)?)
+    .bind(completed)
+    .bind(progress)
+    .bind(&actor)
+    .bind(&actor)
+    .bind(&client_id)
+    .fetch_one(&mut *tx)).await
+    .map_err(task_write_error)?;
+    let task = Task::from_row(&rec, &data)?;
+    let change_id = db_timed(&req, append_change(&mut tx, task.id, if existed { "updated" } else { "created" })).await?;
+    tx.commit().await.map_err(|e| AppError::Internal(e.to_string()))?;
+
+    notify_change(&data, change_id);
+    if existed {
+        Ok(HttpResponse::Ok().json(task))
+    } else {
+        Ok(HttpResponse::Created().json(task))
+    }
 }
 
 #[delete("/api/tasks/{id}")]
 async fn delete_task(req: HttpRequest, path: web::Path<i64>, data: web::Data<AppState>) -> Result<impl Responder, AppError> {
     ensure_auth(&req, &data).await?;
     let id = path.into_inner();
-    let res = sqlx::query("DELETE FROM tasks WHERE id = ?").bind(id).execute(&data.pool).await
+    let mut tx = data.pool.begin().await.map_err(|e| AppError::Internal(e.to_string()))?;
+    let res = db_timed(&req, sqlx::query("DELETE FROM tasks WHERE id = ?").bind(id).execute(&mut *tx)).await
         .map_err(|e| AppError::Internal(e.to_string()))?;
     if res.rows_affected() == 0 {
         return Err(AppError::NotFound);
     }
+    let change_id = db_timed(&req, append_change(&mut tx, id, "deleted")).await?;
+    tx.commit().await.map_err(|e| AppError::Internal(e.to_string()))?;
+
+    notify_change(&data, change_id);
     Ok(HttpResponse::NoContent().finish())
 }
 
-// ---------- Migrations ----------
-
-async fn run_migrations(pool: &SqlitePool) -> Result<(), AppError> {
-    let sql = fs::read_to_string("migrations/001_init.sql")
-        .map_err(|e| AppError::Internal(format!("Failed reading migration: {}", e)))?;
-    sqlx::query(&sql).execute(pool).await.map_err(|e| AppError::Internal(e.to_string()))?;
-    Ok(())
+#[derive(Deserialize, Validate)]
+struct BulkToggle {
+    #[validate(length(min = 1, max = 200, message = "ids must contain 1-200 task ids"))]
+    #[serde(deserialize_with = "deserialize_ids")]
+    ids: Vec<i64>,
 }
 
-// ---------- Main ----------
+/// Flips `completed` on every listed task in one transaction. Unknown ids don't fail the batch;
+/// they are returned in `not_found`, and tasks that can't be completed yet because of open
+/// blockers in `blocked` (unless `?force=true`).
+#[post("/api/tasks/bulk-toggle")]
+async fn bulk_toggle_tasks(
+    req: HttpRequest,
+    force: web::Query<ForceQuery>,
+    data: web::Data<AppState>,
+    payload: web::Json<BulkToggle>,
+) -> Result<impl Responder, AppError> {
+    let actor = ensure_auth(&req, &data).await?;
+    payload.validate().map_err(AppError::Validation)?;
+    check_editable(&data, &actor, &["completed"])?;
+    let mut ids = payload.ids.clone();
+    // A repeated id would otherwise be flipped back.
+    let mut seen = HashSet::new();
+    ids.retain(|id| seen.insert(*id));
 
-#[actix_web::main]
-async fn main() -> std::io::Result<()> {
-    dotenvy::dotenv().ok();
-    env_logger::init();
+    let mut tx = data.pool.begin().await.map_err(|e| AppError::Internal(e.to_string()))?;
+    let mut updated = Vec::new();
+    let mut not_found = Vec::new();
+    let mut blocked = Vec::new();
+    let mut last_change = None;
+    let force = force.force.unwrap_or(false);
+    for id in ids {
+        let rec = db_timed(&req, sqlx::query(&format!(
+            "UPDATE tasks SET completed = NOT completed, progress = CASE WHEN completed THEN 0 ELSE 100 END, updated_by = ? \
+             WHERE id = ? AND (completed = 1 OR ? OR NOT EXISTS ({})) RETURNING {}",
+            INCOMPLETE_BLOCKERS_SQL, TASK_COLUMNS
+        ))
+        .bind(&actor)
+        .bind(id)
+        .bind(force)
+        .bind(id)
+        .fetch_optional(&mut *tx)).await
+        .map_err(|e| AppError::Internal(e.to_string()))?;
+        match rec {
+            Some(rec) => {
+                updated.push(Task::from_row(&rec, &data)?);
+                last_change = Some(db_timed(&req, append_change(&mut tx, id, "updated")).await?);
+            }
+            None if db_timed(&req, task_position(&mut tx, id)).await?.is_some() => blocked.push(TaskId(id)),
+            None => not_found.push(TaskId(id)),
+        }
+    }
+    tx.commit().await.map_err(|e| AppError::Internal(e.to_string()))?;
 
-    let database_url = env::var("DATABASE_URL").unwrap_or_else(|_| "sqlite://data.db".into());
-    let bind_addr = env::var("BIND_ADDR").unwrap_or_else(|_| "0.0.0.0:8080".into());
-    let jwt_secret = env::var("JWT_SECRET").ok();
-    let read_only_without_jwt = env::var("READ_ONLY_WITHOUT_JWT").ok().map(|v| v == "1" || v.to_lowercase() == "true").unwrap_or(true);
-    let jwt_enabled = jwt_secret.is_some();
+    if let Some(change_id) = last_change {
+        notify_change(&data, change_id);
+    }
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "updated": updated,
+        "not_found": not_found,
+        "blocked": blocked,
+    })))
+}
 
-    let pool = SqlitePool::connect(&database_url).await
-        .expect("Failed to connect to SQLite");
+#[derive(Deserialize)]
+struct BatchRequest {
+    operations: Vec<serde_json::Value>,
+}
 
-    run_migrations(&pool).await.expect("Migration failed");
+#[derive(Deserialize)]
+struct BatchQuery {
+    continue_on_error: Option<bool>,
+    force: Option<bool>,
+}
 
-    let state = web::Data::new(AppState {
-        pool,
-        jwt_enabled,
-        jwt_secret,
-        read_only_without_jwt,
-    });
+/// Most operations accepted by one `POST /api/tasks/batch`.
+const MAX_BATCH_OPERATIONS: usize = 200;
 
-    println!("Server running at http://{}/", &bind_addr);
-    println!("JWT enabled: {}", jwt_enabled);
-
-    HttpServer::new(move || {
-        App::new()
-            .wrap(Logger::default())
-            .app_data(state.clone())
-            .service(login)
-            .service(create_task)
-            .service(list_tasks)
-            .service(get_task)
-            .service(update_task)
-            .service(delete_task)
-    })
-    .bind(&bind_addr)?
-    .run()
-    .await
+/// One entry of a batch, checked against the same rules as the single-task endpoints.
+enum BatchOp {
+    Create { title: String, progress: i64, estimate_minutes: Option<i64>, expires_at: Option<String> },
+    Update { id: i64, changes: TaskChanges },
+    Delete { id: i64 },
+}
+
+impl BatchOp {
+    /// Parses `{"op": "create" | "update" | "delete", ...}`. Create takes the `POST /api/tasks`
+    /// fields, update an `id` plus the `PUT` fields, delete only an `id`.
+    fn parse(mut value: serde_json::Value, data: &AppState) -> Result<BatchOp, AppError> {
+        let Some(fields) = value.as_object_mut() else {
+            return Err(AppError::BadRequest("operation must be an object".into()));
+        };
+        let op = match fields.remove("op") {
+            Some(serde_json::Value::String(op)) => op,
+            _ => return Err(AppError::BadRequest("'op' must be one of create, update, delete".into())),
+        };
+        let id = fields.remove("id")
+            .map(|id| serde_json::from_value::<IdRepr>(id)
+                .map_err(|e| e.to_string())
+                .and_then(|id| id.into_id::<serde_json::Error>().map_err(|e| e.to_string())))
+            .transpose()
+            .map_err(|e| AppError::BadRequest(format!("invalid id: {}", e)))?;
+        match (op.as_str(), id) {
+            ("create", None) => {
+                let payload: CreateTask = parse_body(value, data)?;
+                payload.validate().map_err(AppError::Validation)?;
+                let (_, progress) = resolve_progress(false, 0, None, payload.progress)?;
+                Ok(BatchOp::Create {
+                    title: clean_title(data, &payload.title)?,
+                    progress,
+                    estimate_minutes: payload.estimate_minutes,
+                    expires_at: payload.ttl_seconds.map(expires_at_from_ttl).transpose()?,
+                })
+            }
+            ("create", Some(_)) => Err(AppError::BadRequest("create does not take an id".into())),
+            ("update", Some(id)) => {
+                let payload: UpdateTask = parse_body(value, data)?;
+                payload.validate().map_err(AppError::Validation)?;
+                let changes = TaskChanges {
+                    title: payload.title.as_deref().map(|t| clean_title(data, t)).transpose()?,
+                    completed: payload.completed,
+                    progress: payload.progress,
+                    estimate_minutes: payload.estimate_minutes.map(Some),
+                };
+                Ok(BatchOp::Update { id, changes })
+            }
+            ("delete", Some(id)) if fields.is_empty() => Ok(BatchOp::Delete { id }),
+            ("delete", Some(_)) => Err(AppError::BadRequest("delete only takes an id".into())),
+            ("update" | "delete", None) => Err(AppError::BadRequest(format!("{} requires an id", op))),
+            (other, _) => Err(AppError::BadRequest(format!("unsupported op '{}'", other))),
+        }
+    }
+
+    async fn execute(
+        self,
+        req: &HttpRequest,
+        conn: &mut SqliteConnection,
+        data: &AppState,
+        actor: &str,
+        force: bool,
+    ) -> Result<(serde_json::Value, i64), AppError> {
+        match self {
+            BatchOp::Create { title, progress, estimate_minutes, expires_at } => {
+                let task = insert_task(req, conn, data, actor, &title, progress, estimate_minutes, expires_at).await?;
+                let change_id = db_timed(req, append_change(conn, task.id, "created")).await?;
+                Ok((serde_json::json!({ "status": 201, "task": task }), change_id))
+            }
+            BatchOp::Update { id, changes } => {
+                let (task, change_id) = update_task_row(req, conn, data, actor, id, changes, force).await?;
+                Ok((serde_json::json!({ "status": 200, "task": task }), change_id))
+            }
+            BatchOp::Delete { id } => {
+                let res = db_timed(req, sqlx::query("DELETE FROM tasks WHERE id = ?").bind(id).execute(&mut *conn)).await
+                    .map_err(|e| AppError::Internal(e.to_string()))?;
+                if res.rows_affected() == 0 {
+                    return Err(AppError::NotFound);
+                }
+                let change_id = db_timed(req, append_change(conn, id, "deleted")).await?;
+                Ok((serde_json::json!({ "status": 204, "id": TaskId(id) }), change_id))
+            }
+        }
+    }
+}
+
+/// `{"error": message, "index": index}` with `e`'s status, plus `fields` for validation errors, so a
+/// failing operation is reported like the single-task endpoint would report it.
+fn batch_error(e: &AppError, index: usize, message: String) -> HttpResponse {
+    let status = actix_web::ResponseError::error_response(e).status();
+    let mut body = serde_json::json!({ "error": message, "index": index });
+    if let AppError::Validation(errors) = e {
+        body["fields"] = serde_json::json!(field_messages(errors));
+    }
+    HttpResponse::build(status).json(body)
+}
+
+/// Runs mixed create/update/delete operations in order, in one transaction, and returns one result
+/// per operation. Every operation is parsed and validated before any is run, so a malformed one
+/// rejects the batch with 400 (422 for validation errors) and its `index`. By default the first failing operation rolls everything back and its
+/// status is returned; with `?continue_on_error=true` only that operation is undone (each runs in
+/// its own savepoint) and the rest still commit.
+#[post("/api/tasks/batch")]
+async fn batch_tasks(
+    req: HttpRequest,
+    query: web::Query<BatchQuery>,
+    data: web::Data<AppState>,
+    payload: web::Json<BatchRequest>,
+) -> Result<impl Responder, AppError> {
+    let actor = ensure_auth(&req, &data).await?;
+    let payload = payload.into_inner();
+    if payload.operations.is_empty() || payload.operations.len() > MAX_BATCH_OPERATIONS {
+        return Err(AppError::BadRequest(format!("operations must contain 1-{} entries", MAX_BATCH_OPERATIONS)));
+    }
+    let mut ops = Vec::with_capacity(payload.operations.len());
+    for (i, op) in payload.operations.into_iter().enumerate() {
+        match BatchOp::parse(op, &data) {
+            Ok(op) => ops.push(op),
+            Err(e) => {
+                let detail = match &e {
+                    AppError::BadRequest(msg) => msg.clone(),
+                    AppError::Validation(errors) => errors.to_string(),
+                    other => other.to_string(),
+                };
+                return Ok(batch_error(&e, i, format!("operation {}: {}", i, detail)));
+            }
+        }
+    }
+    let continue_on_error = query.continue_on_error.unwrap_or(false);
+    let force = query.force.unwrap_or(false);
+
+    let mut tx = data.pool.begin().await.map_err(|e| AppError::Internal(e.to_string()))?;
+    let mut results = Vec::with_capacity(ops.len());
+    let mut change_ids = Vec::new();
+    for (i, op) in ops.into_iter().enumerate() {
+        let mut savepoint = (*tx).begin().await.map_err(|e| AppError::Internal(e.to_string()))?;
+        match op.execute(&req, &mut savepoint, &data, &actor, force).await {
+            Ok((result, change_id)) => {
+                savepoint.commit().await.map_err(|e| AppError::Internal(e.to_string()))?;
+                results.push(result);
+                change_ids.push(change_id);
+            }
+            Err(e) if continue_on_error => {
+                savepoint.rollback().await.map_err(|e| AppError::Internal(e.to_string()))?;
+                let status = actix_web::ResponseError::error_response(&e).status();
+                results.push(serde_json::json!({ "status": status.as_u16(), "error": e.to_string() }));
+            }
+            Err(e) => {
+                drop(savepoint);
+                tx.rollback().await.map_err(|e| AppError::Internal(e.to_string()))?;
+                return Ok(batch_error(&e, i, format!("operation {} failed, batch rolled back: {}", i, e)));
+            }
+        }
+    }
+    tx.commit().await.map_err(|e| AppError::Internal(e.to_string()))?;
+
+    for change_id in change_ids {
+        notify_change(&data, change_id);
+    }
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "results": results })))
+}
+
+#[derive(Deserialize)]
+struct MoveTask {
+    #[serde(default, deserialize_with = "deserialize_opt_id")]
+    after_id: Option<i64>,
+    #[serde(default, deserialize_with = "deserialize_opt_id")]
+    before_id: Option<i64>,
+}
+
+async fn task_position(conn: &mut SqliteConnection, id: i64) -> Result<Option<f64>, AppError> {
+    sqlx::query_scalar("SELECT position FROM tasks WHERE id = ?")
+        .bind(id)
+        .fetch_optional(conn).await
+        .map_err(|e| AppError::Internal(e.to_string()))
+}
+
+/// Position strictly between the anchor and its neighbour on the requested side (ignoring the
+/// task being moved), or `None` when the two are too close to split and a rebalance is needed.
+async fn position_next_to(conn: &mut SqliteConnection, moving_id: i64, anchor: f64, after: bool) -> Result<Option<f64>, AppError> {
+    let sql = if after {
+        "SELECT MIN(position) FROM tasks WHERE position > ? AND id != ?"
+    } else {
+        "SELECT MAX(position) FROM tasks WHERE position < ? AND id != ?"
+    };
+    let neighbour: Option<f64> = sqlx::query_scalar(sql)
+        .bind(anchor)
+        .bind(moving_id)
+        .fetch_one(conn).await
+        .map_err(|e| AppError::Internal(e.to_string()))?;
+    let candidate = match neighbour {
+        Some(n) => (anchor + n) / 2.0,
+        None if after => anchor + POSITION_GAP,
+        None => anchor - POSITION_GAP,
+    };
+    if candidate == anchor || Some(candidate) == neighbour {
+        return Ok(None);
+    }
+    Ok(Some(candidate))
+}
+
+/// Respaces every task `POSITION_GAP` apart, keeping the current order.
+async fn rebalance_positions(conn: &mut SqliteConnection) -> Result<(), AppError> {
+    sqlx::query(
+        "UPDATE tasks SET position = (SELECT rn FROM (SELECT id, ROW_NUMBER() OVER (ORDER BY position, id) AS rn FROM tasks) r WHERE r.id = tasks.id) * ?"
+    )
+    .bind(POSITION_GAP)
+    .execute(conn).await
+    .map_err(|e| AppError::Internal(e.to_string()))?;
+    Ok(())
+}
+
+#[derive(Deserialize)]
+struct SnoozeTask {
+    until: Option<DateTime<Utc>>,
+}
+
+/// Hides the task from lists until `until`; it reappears by itself once that time has passed.
+/// Snoozing again replaces the previous time.
+#[post("/api/tasks/{id}/snooze")]
+async fn snooze_task(
+    req: HttpRequest,
+    path: web::Path<i64>,
+    data: web::Data<AppState>,
+    payload: web::Json<SnoozeTask>,
+) -> Result<impl Responder, AppError> {
+    let actor = ensure_auth(&req, &data).await?;
+    let until = payload.until.ok_or_else(|| field_error("until", "required", "until is required"))?;
+    if until <= Utc::now() {
+        return Err(field_error("until", "future", "until must be in the future"));
+    }
+    let id = path.into_inner();
+    let mut tx = data.pool.begin().await.map_err(|e| AppError::Internal(e.to_string()))?;
+    db_timed(&req, check_field_writable(&mut tx, &data, &actor, id, "snoozed_until")).await?;
+    let rec = db_timed(&req, sqlx::query(&format!("UPDATE tasks SET snoozed_until = ?, updated_by = ? WHERE id = ? RETURNING {}", TASK_COLUMNS))
+        .bind(until.format("%Y-%m-%d %H:%M:%S").to_string())
+        .bind(&actor)
+        .bind(id)
+        .fetch_one(&mut *tx)).await
+        .map_err(|e| AppError::Internal(e.to_string()))?;
+    let change_id = db_timed(&req, append_change(&mut tx, id, "updated")).await?;
+    tx.commit().await.map_err(|e| AppError::Internal(e.to_string()))?;
+
+    notify_change(&data, change_id);
+    Ok(HttpResponse::Ok().json(Task::from_row(&rec, &data)?))
+}
+
+/// Moves a task directly after `after_id` or before `before_id` by giving it a position between
+/// its new neighbours, so normally only the moved row is written.
+#[post("/api/tasks/{id}/move")]
+async fn move_task(
+    req: HttpRequest,
+    path: web::Path<i64>,
+    data: web::Data<AppState>,
+    payload: web::Json<MoveTask>,
+) -> Result<impl Responder, AppError> {
+    let actor = ensure_auth(&req, &data).await?;
+    let id = path.into_inner();
+    let (anchor_id, after) = match (payload.after_id, payload.before_id) {
+        (Some(after_id), None) => (after_id, true),
+        (None, Some(before_id)) => (before_id, false),
+        _ => return Err(AppError::BadRequest("provide exactly one of after_id or before_id".into())),
+    };
+    if anchor_id == id {
+        return Err(AppError::BadRequest("cannot move a task relative to itself".into()));
+    }
+
+    let mut tx = data.pool.begin().await.map_err(|e| AppError::Internal(e.to_string()))?;
+    db_timed(&req, check_field_writable(&mut tx, &data, &actor, id, "position")).await?;
+    let Some(anchor) = db_timed(&req, task_position(&mut tx, anchor_id)).await? else {
+        return Err(AppError::BadRequest(format!("task {} does not exist", anchor_id)));
+    };
+    let position = match db_timed(&req, position_next_to(&mut tx, id, anchor, after)).await? {
+        Some(position) => position,
+        None => {
+            db_timed(&req, rebalance_positions(&mut tx)).await?;
+            let anchor = db_timed(&req, task_position(&mut tx, anchor_id)).await?.unwrap_or(anchor);
+            db_timed(&req, position_next_to(&mut tx, id, anchor, after)).await?
+                .ok_or_else(|| AppError::Internal("no free position after rebalance".into()))?
+        }
+    };
+    let rec = db_timed(&req, sqlx::query(&format!("UPDATE tasks SET position = ?, updated_by = ? WHERE id = ? RETURNING {}", TASK_COLUMNS))
+        .bind(position)
+        .bind(&actor)
+        .bind(id)
+        .fetch_one(&mut *tx)).await
+        .map_err(|e| AppError::Internal(e.to_string()))?;
+    let change_id = db_timed(&req, append_change(&mut tx, id, "updated")).await?;
+    tx.commit().await.map_err(|e| AppError::Internal(e.to_string()))?;
+
+    notify_change(&data, change_id);
+    Ok(HttpResponse::Ok().json(Task::from_row(&rec, &data)?))
+}
+
+/// JSON Schemas derived from the request structs, for client-side form generation and validation.
+#[get("/api/tasks/schema")]
+async fn task_schema(data: web::Data<AppState>) -> impl Responder {
+    let mut create = serde_json::to_value(schema_for!(CreateTask)).unwrap_or_default();
+    let mut update = serde_json::to_value(schema_for!(UpdateTask)).unwrap_or_default();
+    // MAX_TITLE_LEN is runtime config, so it's not visible to the derive.
+    for schema in [&mut create, &mut update] {
+        if let Some(title) = schema.pointer_mut("/properties/title").and_then(|t| t.as_object_mut()) {
+            title.insert("maxLength".into(), serde_json::json!(data.max_title_len));
+        }
+    }
+    HttpResponse::Ok().json(serde_json::json!({
+        "CreateTask": create,
+        "UpdateTask": update,
+    }))
+}
+
+/// Maps a failed task insert/update, turning a hit on the `ENFORCE_UNIQUE_TITLES` index into a 409.
+fn task_write_error(e: sqlx::Error) -> AppError {
+    if let Some(db) = e.as_database_error() {
+        if db.is_unique_violation() && db.message().contains(UNIQUE_TITLE_INDEX) {
+            return AppError::Conflict("you already have a task with this title".into());
+        }
+    }
+    AppError::Internal(e.to_string())
+}
+
+/// Path extraction failures. A numeric id too large for i64 is a client error (400); anything else
+/// (e.g. `/api/tasks/abc`) keeps actix's default of 404.
+fn path_error(err: actix_web::error::PathError, req: &HttpRequest) -> actix_web::Error {
+    let overflowing = req.match_info().iter().any(|(_, value)| {
+        let digits = value.strip_prefix('-').unwrap_or(value);
+        !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit()) && value.parse::<i64>().is_err()
+    });
+    if overflowing {
+        AppError::BadRequest("task id out of range".into()).into()
+    } else {
+        actix_web::error::ErrorNotFound(err)
+    }
+}
+
+async fn task_exists(pool: &SqlitePool, id: i64) -> Result<bool, AppError> {
+    let rec = sqlx::query("SELECT 1 FROM tasks WHERE id = ?")
+        .bind(id)
+        .fetch_optional(pool).await
+        .map_err(|e| AppError::Internal(e.to_string()))?;
+    Ok(rec.is_some())
+}
+
+// ---------- Attachments ----------
+
+#[derive(Serialize)]
+struct Attachment {
+    id: i64,
+    #[serde(serialize_with = "serialize_id")]
+    task_id: i64,
+    url: String,
+    created_at: String,
+}
+
+impl Attachment {
+    fn from_row(rec: &SqliteRow) -> Attachment {
+        Attachment {
+            id: rec.get::<i64, _>("id"),
+            task_id: rec.get::<i64, _>("task_id"),
+            url: rec.get::<String, _>("url"),
+            created_at: rec.get::<String, _>("created_at"),
+        }
+    }
+}
+
+#[derive(Deserialize, Validate)]
+struct CreateAttachment {
+    #[validate(custom(function = "validate_http_url"))]
+    url: String,
+}
+
+fn validate_http_url(value: &str) -> Result<(), ValidationError> {
+    match url::Url::parse(value) {
+        Ok(u) if u.scheme() == "http" || u.scheme() == "https" => Ok(()),
+        _ => Err(ValidationError::new("url").with_message("url must be a valid http(s) URL".into())),
+    }
+}
+
+async fn fetch_attachments(pool: &SqlitePool, task_id: i64) -> Result<Vec<Attachment>, AppError> {
+    let rows = sqlx::query("SELECT id, task_id, url, created_at FROM attachments WHERE task_id = ? ORDER BY id ASC")
+        .bind(task_id)
+        .fetch_all(pool).await
+        .map_err(|e| AppError::Internal(e.to_string()))?;
+    Ok(rows.iter().map(Attachment::from_row).collect())
+}
+
+#[post("/api/tasks/{id}/attachments")]
+async fn add_attachment(
+    req: HttpRequest,
+    path: web::Path<i64>,
+    data: web::Data<AppState>,
+    payload: web::Json<CreateAttachment>,
+) -> Result<impl Responder, AppError> {
+    ensure_auth(&req, &data).await?;
+    payload.validate().map_err(AppError::Validation)?;
+    let task_id = path.into_inner();
+    if !db_timed(&req, task_exists(&data.pool, task_id)).await? {
+        return Err(AppError::NotFound);
+    }
+    // Insert first, then count in the same transaction: the insert takes SQLite's write lock, so
+    // concurrent uploads queue behind it and can't both pass the cap.
+    let mut tx = data.pool.begin().await.map_err(|e| AppError::Internal(e.to_string()))?;
+    let rec = db_timed(&req, sqlx::query("INSERT INTO attachments (task_id, url) VALUES (?, ?) RETURNING id, task_id, url, created_at")
+        .bind(task_id)
+        .bind(&payload.url)
+        .fetch_one(&mut *tx)).await
+        .map_err(|e| AppError::Internal(e.to_string()))?;
+    let count: i64 = db_timed(&req, sqlx::query_scalar("SELECT COUNT(*) FROM attachments WHERE task_id = ?")
+        .bind(task_id)
+        .fetch_one(&mut *tx)).await
+        .map_err(|e| AppError::Internal(e.to_string()))?;
+    if count > data.max_attachments_per_task {
+        tx.rollback().await.map_err(|e| AppError::Internal(e.to_string()))?;
+        return Err(AppError::BadRequest(format!(
+            "task already has the maximum of {} attachments", data.max_attachments_per_task
+        )));
+    }
+    tx.commit().await.map_err(|e| AppError::Internal(e.to_string()))?;
+    Ok(HttpResponse::Created().json(Attachment::from_row(&rec)))
+}
+
+#[get("/api/tasks/{id}/attachments")]
+async fn list_attachments(req: HttpRequest, path: web::Path<i64>, data: web::Data<AppState>) -> Result<impl Responder, AppError> {
+    let task_id = path.into_inner();
+    if !db_timed(&req, task_exists(&data.pool, task_id)).await? {
+        return Err(AppError::NotFound);
+    }
+    Ok(HttpResponse::Ok().json(db_timed(&req, fetch_attachments(&data.pool, task_id)).await?))
+}
+
+#[delete("/api/tasks/{id}/attachments/{attachment_id}")]
+async fn delete_attachment(
+    req: HttpRequest,
+    path: web::Path<(i64, i64)>,
+    data: web::Data<AppState>,
+) -> Result<impl Responder, AppError> {
+    ensure_auth(&req, &data).await?;
+    let (task_id, attachment_id) = path.into_inner();
+    let res = db_timed(&req, sqlx::query("DELETE FROM attachments WHERE id = ? AND task_id = ?")
+        .bind(attachment_id)
+        .bind(task_id)
+        .execute(&data.pool)).await
+        .map_err(|e| AppError::Internal(e.to_string()))?;
+    if res.rows_affected() == 0 {
+        return Err(AppError::NotFound);
+    }
+    Ok(HttpResponse::NoContent().finish())
+}
+
+// ---------- Comments ----------
+
+#[derive(Serialize)]
+struct Comment {
+    id: i64,
+    #[serde(serialize_with = "serialize_id")]
+    task_id: i64,
+    author: String,
+    body: String,
+    created_at: String,
+}
+
+impl Comment {
+    fn from_row(rec: &SqliteRow) -> Comment {
+        Comment {
+            id: rec.get::<i64, _>("id"),
+            task_id: rec.get::<i64, _>("task_id"),
+            author: rec.get::<String, _>("author"),
+            body: rec.get::<String, _>("body"),
+            created_at: rec.get::<String, _>("created_at"),
+        }
+    }
+}
+
+#[derive(Deserialize, Validate)]
+struct CreateComment {
+    #[validate(length(min = 1, max = 5000, message = "body must be 1-5000 characters"))]
+    body: String,
+}
+
+async fn count_comments(pool: &SqlitePool, task_id: i64) -> Result<i64, AppError> {
+    sqlx::query_scalar("SELECT COUNT(*) FROM comments WHERE task_id = ?")
+        .bind(task_id)
+        .fetch_one(pool).await
+        .map_err(|e| AppError::Internal(e.to_string()))
+}
+
+#[post("/api/tasks/{id}/comments")]
+async fn add_comment(
+    req: HttpRequest,
+    path: web::Path<i64>,
+    data: web::Data<AppState>,
+    payload: web::Json<CreateComment>,
+) -> Result<impl Responder, AppError> {
+    let actor = ensure_auth(&req, &data).await?;
+    payload.validate().map_err(AppError::Validation)?;
+    let task_id = path.into_inner();
+    if !db_timed(&req, task_exists(&data.pool, task_id)).await? {
+        return Err(AppError::NotFound);
+    }
+    let rec = db_timed(&req, sqlx::query("INSERT INTO comments (task_id, author, body) VALUES (?, ?, ?) RETURNING id, task_id, author, body, created_at")
+        .bind(task_id)
+        .bind(&actor)
+        .bind(&payload.body)
+        .fetch_one(&data.pool)).await
+        .map_err(|e| AppError::Internal(e.to_string()))?;
+    Ok(HttpResponse::Created().json(Comment::from_row(&rec)))
+}
+
+/// Oldest first, paginated with `?limit=` (default 50, max 200) and `?offset=`.
+#[get("/api/tasks/{id}/comments")]
+async fn list_comments(
+    req: HttpRequest,
+    path: web::Path<i64>,
+    query: web::Query<PageQuery>,
+    data: web::Data<AppState>,
+) -> Result<impl Responder, AppError> {
+    let task_id = path.into_inner();
+    if !db_timed(&req, task_exists(&data.pool, task_id)).await? {
+        return Err(AppError::NotFound);
+    }
+    let (limit, offset) = query.resolve()?;
+    let rows = db_timed(&req, sqlx::query("SELECT id, task_id, author, body, created_at FROM comments WHERE task_id = ? ORDER BY id ASC LIMIT ? OFFSET ?")
+        .bind(task_id)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(&data.pool)).await
+        .map_err(|e| AppError::Internal(e.to_string()))?;
+    let total = db_timed(&req, count_comments(&data.pool, task_id)).await?;
+    Ok(HttpResponse::Ok().json(Page {
+        items: rows.iter().map(Comment::from_row).collect(),
+        total,
+        limit,
+        offset,
+    }))
+}
+
+// ---------- Dependencies ----------
+
+#[derive(Deserialize)]
+struct ForceQuery {
+    /// Complete a task even though it still has incomplete blockers.
+    force: Option<bool>,
+}
+
+/// Ids of incomplete tasks blocking task `?`.
+const INCOMPLETE_BLOCKERS_SQL: &str =
+    "SELECT b.id FROM task_dependencies d JOIN tasks b ON b.id = d.blocked_by_id WHERE d.task_id = ? AND b.completed = 0";
+
+/// 409 if the task still has incomplete blockers; checked whenever a task goes from open to completed.
+async fn check_not_blocked(conn: &mut SqliteConnection, id: i64) -> Result<(), AppError> {
+    let blockers: Vec<i64> = sqlx::query_scalar(&format!("{} ORDER BY b.id", INCOMPLETE_BLOCKERS_SQL))
+        .bind(id)
+        .fetch_all(conn).await
+        .map_err(|e| AppError::Internal(e.to_string()))?;
+    if !blockers.is_empty() {
+        return Err(AppError::Conflict(format!(
+            "task is blocked by incomplete tasks {:?}; complete them first or pass ?force=true", blockers
+        )));
+    }
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct Dependency {
+    #[serde(serialize_with = "serialize_id")]
+    task_id: i64,
+    #[serde(serialize_with = "serialize_id")]
+    blocked_by_id: i64,
+    created_at: String,
+}
+
+#[derive(Deserialize)]
+struct CreateDependency {
+    #[serde(deserialize_with = "deserialize_opt_id")]
+    blocked_by: Option<i64>,
+}
+
+/// Records that task `{id}` is blocked by `blocked_by`. Adding an existing dependency is a no-op
+/// (200); a dependency that would close a cycle is rejected with 409.
+#[post("/api/tasks/{id}/dependencies")]
+async fn add_dependency(
+    req: HttpRequest,
+    path: web::Path<i64>,
+    data: web::Data<AppState>,
+    payload: web::Json<CreateDependency>,
+) -> Result<impl Responder, AppError> {
+    ensure_auth(&req, &data).await?;
+    let id = path.into_inner();
+    let blocked_by = payload.blocked_by.ok_or_else(|| field_error("blocked_by", "required", "blocked_by is required"))?;
+    if blocked_by == id {
+        return Err(field_error("blocked_by", "self", "a task cannot block itself"));
+    }
+
+    let mut tx = data.pool.begin().await.map_err(|e| AppError::Internal(e.to_string()))?;
+    if db_timed(&req, task_position(&mut tx, id)).await?.is_none() {
+        return Err(AppError::NotFound);
+    }
+    if db_timed(&req, task_position(&mut tx, blocked_by)).await?.is_none() {
+        return Err(AppError::BadRequest(format!("task {} does not exist", blocked_by)));
+    }
+    // Adding id -> blocked_by closes a cycle if id is already among blocked_by's (transitive) blockers.
+    let cycle = db_timed(&req, sqlx::query(
+        "WITH RECURSIVE chain(id) AS ( \
+             SELECT blocked_by_id FROM task_dependencies WHERE task_id = ? \
+             UNION SELECT d.blocked_by_id FROM task_dependencies d JOIN chain c ON d.task_id = c.id \
+         ) SELECT 1 FROM chain WHERE id = ?"
+    )
+    .bind(blocked_by)
+    .bind(id)
+    .fetch_optional(&mut *tx)).await
+    .map_err(|e| AppError::Internal(e.to_string()))?;
+    if cycle.is_some() {
+        return Err(AppError::Conflict(format!("task {} already depends on task {}; this would create a cycle", blocked_by, id)));
+    }
+    let inserted = db_timed(&req, sqlx::query("INSERT OR IGNORE INTO task_dependencies (task_id, blocked_by_id) VALUES (?, ?)")
+        .bind(id)
+        .bind(blocked_by)
+        .execute(&mut *tx)).await
+        .map_err(|e| AppError::Internal(e.to_string()))?
+        .rows_affected() > 0;
+    let rec = db_timed(&req, sqlx::query("SELECT task_id, blocked_by_id, created_at FROM task_dependencies WHERE task_id = ? AND blocked_by_id = ?")
+        .bind(id)
+        .bind(blocked_by)
+        .fetch_one(&mut *tx)).await
+        .map_err(|e| AppError::Internal(e.to_string()))?;
+    tx.commit().await.map_err(|e| AppError::Internal(e.to_string()))?;
+
+    let dependency = Dependency {
+        task_id: rec.get::<i64, _>("task_id"),
+        blocked_by_id: rec.get::<i64, _>("blocked_by_id"),
+        created_at: rec.get::<String, _>("created_at"),
+    };
+    if inserted {
+        Ok(HttpResponse::Created().json(dependency))
+    } else {
+        Ok(HttpResponse::Ok().json(dependency))
+    }
+}
+
+#[delete("/api/tasks/{id}/dependencies/{blocked_by_id}")]
+async fn delete_dependency(
+    req: HttpRequest,
+    path: web::Path<(i64, i64)>,
+    data: web::Data<AppState>,
+) -> Result<impl Responder, AppError> {
+    ensure_auth(&req, &data).await?;
+    let (id, blocked_by_id) = path.into_inner();
+    let res = db_timed(&req, sqlx::query("DELETE FROM task_dependencies WHERE task_id = ? AND blocked_by_id = ?")
+        .bind(id)
+        .bind(blocked_by_id)
+        .execute(&data.pool)).await
+        .map_err(|e| AppError::Internal(e.to_string()))?;
+    if res.rows_affected() == 0 {
+        return Err(AppError::NotFound);
+    }
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// The task's direct blockers that are not completed yet, paginated like comments.
+#[get("/api/tasks/{id}/blockers")]
+async fn list_blockers(
+    req: HttpRequest,
+    path: web::Path<i64>,
+    query: web::Query<PageQuery>,
+    data: web::Data<AppState>,
+) -> Result<impl Responder, AppError> {
+    let id = path.into_inner();
+    if !db_timed(&req, task_exists(&data.pool, id)).await? {
+        return Err(AppError::NotFound);
+    }
+    let (limit, offset) = query.resolve()?;
+    let rows = db_timed(&req, sqlx::query(&format!(
+        "SELECT {} FROM tasks WHERE id IN ({}) ORDER BY id ASC LIMIT ? OFFSET ?", TASK_COLUMNS, INCOMPLETE_BLOCKERS_SQL
+    ))
+    .bind(id)
+    .bind(limit)
+    .bind(offset)
+    .fetch_all(&data.pool)).await
+    .map_err(|e| AppError::Internal(e.to_string()))?;
+    let total: i64 = db_timed(&req, sqlx::query_scalar(&format!("SELECT COUNT(*) FROM ({})", INCOMPLETE_BLOCKERS_SQL))
+        .bind(id)
+        .fetch_one(&data.pool)).await
+        .map_err(|e| AppError::Internal(e.to_string()))?;
+    Ok(HttpResponse::Ok().json(Page {
+        items: rows.iter().map(|rec| Task::from_row(rec, &data)).collect::<Result<Vec<_>, _>>()?,
+        total,
+        limit,
+        offset,
+    }))
+}
+
+// ---------- Merging ----------
+
+#[derive(Deserialize)]
+struct MergeTask {
+    #[serde(deserialize_with = "deserialize_opt_id")]
+    source_id: Option<i64>,
+}
+
+/// Folds a duplicate into this task: the source's comments, attachments, reminders and
+/// dependencies move over, then the source is deleted, all in one transaction. Both tasks must
+/// have been created by the caller (admins may merge any). Dependencies that would point the task
+/// at itself are dropped; a merge that would create a dependency cycle is a 409. The change log gets
+/// a plain `deleted` for the source and `updated` for the target, so pollers need no merge-specific op.
+#[post("/api/tasks/{id}/merge")]
+async fn merge_task(
+    req: HttpRequest,
+    path: web::Path<i64>,
+    data: web::Data<AppState>,
+    payload: web::Json<MergeTask>,
+) -> Result<impl Responder, AppError> {
+    let actor = ensure_auth(&req, &data).await?;
+    let id = path.into_inner();
+    let source_id = payload.source_id.ok_or_else(|| AppError::BadRequest("source_id is required".into()))?;
+    if source_id == id {
+        return Err(AppError::BadRequest("a task cannot be merged into itself".into()));
+    }
+
+    let mut tx = data.pool.begin().await.map_err(|e| AppError::Internal(e.to_string()))?;
+    for task_id in [id, source_id] {
+        let owner: Option<String> = db_timed(&req, sqlx::query_scalar("SELECT created_by FROM tasks WHERE id = ?")
+            .bind(task_id)
+            .fetch_optional(&mut *tx)).await
+            .map_err(|e| AppError::Internal(e.to_string()))?;
+        match owner {
+            None => return Err(AppError::NotFound),
+            Some(owner) if owner != actor && !data.admin_users.contains(&actor) => return Err(AppError::Forbidden),
+            Some(_) => {}
+        }
+    }
+
+    let attachments: i64 = db_timed(&req, sqlx::query_scalar("SELECT COUNT(*) FROM attachments WHERE task_id IN (?, ?)")
+        .bind(id)
+        .bind(source_id)
+        .fetch_one(&mut *tx)).await
+        .map_err(|e| AppError::Internal(e.to_string()))?;
+    if attachments > data.max_attachments_per_task {
+        return Err(AppError::Conflict(format!(
+            "merged task would have {} attachments; the maximum is {}", attachments, data.max_attachments_per_task
+        )));
+    }
+    for table in ["comments", "attachments", "reminders"] {
+        db_timed(&req, sqlx::query(&format!("UPDATE {} SET task_id = ? WHERE task_id = ?", table))
+            .bind(id)
+            .bind(source_id)
+            .execute(&mut *tx)).await
+            .map_err(|e| AppError::Internal(e.to_string()))?;
+    }
+    db_timed(&req, sqlx::query(
+        "INSERT OR IGNORE INTO task_dependencies (task_id, blocked_by_id) \
+         SELECT ?, blocked_by_id FROM task_dependencies WHERE task_id = ? AND blocked_by_id != ? \
+         UNION SELECT task_id, ? FROM task_dependencies WHERE blocked_by_id = ? AND task_id != ?"
+    )
+    .bind(id)
+    .bind(source_id)
+    .bind(id)
+    .bind(id)
+    .bind(source_id)
+    .bind(id)
+    .execute(&mut *tx)).await
+    .map_err(|e| AppError::Internal(e.to_string()))?;
+    // Also drops the source's original dependency rows.
+    db_timed(&req, sqlx::query("DELETE FROM tasks WHERE id = ?")
+        .bind(source_id)
+        .execute(&mut *tx)).await
+        .map_err(|e| AppError::Internal(e.to_string()))?;
+    let cycle = db_timed(&req, sqlx::query(
+        "WITH RECURSIVE chain(id) AS ( \
+             SELECT blocked_by_id FROM task_dependencies WHERE task_id = ? \
+             UNION SELECT d.blocked_by_id FROM task_dependencies d JOIN chain c ON d.task_id = c.id \
+         ) SELECT 1 FROM chain WHERE id = ?"
+    )
+    .bind(id)
+    .bind(id)
+    .fetch_optional(&mut *tx)).await
+    .map_err(|e| AppError::Internal(e.to_string()))?;
+    if cycle.is_some() {
+        return Err(AppError::Conflict(format!("merging task {} into task {} would create a dependency cycle", source_id, id)));
+    }
+    let rec = db_timed(&req, sqlx::query(&format!("UPDATE tasks SET updated_by = ? WHERE id = ? RETURNING {}", TASK_COLUMNS))
+        .bind(&actor)
+        .bind(id)
+        .fetch_one(&mut *tx)).await
+        .map_err(|e| AppError::Internal(e.to_string()))?;
+    let deleted_change = db_timed(&req, append_change(&mut tx, source_id, "deleted")).await?;
+    let updated_change = db_timed(&req, append_change(&mut tx, id, "updated")).await?;
+    tx.commit().await.map_err(|e| AppError::Internal(e.to_string()))?;
+
+    notify_change(&data, deleted_change);
+    notify_change(&data, updated_change);
+    log::info!("{} merged task {} into task {}", actor, source_id, id);
+    Ok(HttpResponse::Ok().json(Task::from_row(&rec, &data)?))
+}
+
+// ---------- Reminders ----------
+
+const REMINDER_COLUMNS: &str = "id, task_id, remind_at, sent_at, created_at";
+
+#[derive(Serialize)]
+struct Reminder {
+    id: i64,
+    #[serde(serialize_with = "serialize_id")]
+    task_id: i64,
+    remind_at: String,
+    sent_at: Option<String>,
+    created_at: String,
+}
+
+impl Reminder {
+    fn from_row(rec: &SqliteRow) -> Reminder {
+        Reminder {
+            id: rec.get::<i64, _>("id"),
+            task_id: rec.get::<i64, _>("task_id"),
+            remind_at: rec.get::<String, _>("remind_at"),
+            sent_at: rec.get::<Option<String>, _>("sent_at"),
+            created_at: rec.get::<String, _>("created_at"),
+        }
+    }
+}
+
+/// `remind_at` is an RFC 3339 timestamp, e.g. `2030-01-01T09:00:00Z`.
+#[derive(Deserialize)]
+struct CreateReminder {
+    remind_at: DateTime<Utc>,
+}
+
+#[post("/api/tasks/{id}/reminders")]
+async fn add_reminder(
+    req: HttpRequest,
+    path: web::Path<i64>,
+    data: web::Data<AppState>,
+    payload: web::Json<CreateReminder>,
+) -> Result<impl Responder, AppError> {
+    ensure_auth(&req, &data).await?;
+    if payload.remind_at <= Utc::now() {
+        return Err(field_error("remind_at", "future", "remind_at must be in the future"));
+    }
+    let task_id = path.into_inner();
+    if !db_timed(&req, task_exists(&data.pool, task_id)).await? {
+        return Err(AppError::NotFound);
+    }
+    let rec = db_timed(&req, sqlx::query(&format!("INSERT INTO reminders (task_id, remind_at) VALUES (?, ?) RETURNING {}", REMINDER_COLUMNS))
+        .bind(task_id)
+        .bind(payload.remind_at.format("%Y-%m-%d %H:%M:%S").to_string())
+        .fetch_one(&data.pool)).await
+        .map_err(|e| AppError::Internal(e.to_string()))?;
+    Ok(HttpResponse::Created().json(Reminder::from_row(&rec)))
+}
+
+#[get("/api/tasks/{id}/reminders")]
+async fn list_reminders(req: HttpRequest, path: web::Path<i64>, data: web::Data<AppState>) -> Result<impl Responder, AppError> {
+    let task_id = path.into_inner();
+    if !db_timed(&req, task_exists(&data.pool, task_id)).await? {
+        return Err(AppError::NotFound);
+    }
+    let rows = db_timed(&req, sqlx::query(&format!("SELECT {} FROM reminders WHERE task_id = ? ORDER BY remind_at ASC, id ASC", REMINDER_COLUMNS))
+        .bind(task_id)
+        .fetch_all(&data.pool)).await
+        .map_err(|e| AppError::Internal(e.to_string()))?;
+    Ok(HttpResponse::Ok().json(rows.iter().map(Reminder::from_row).collect::<Vec<_>>()))
+}
+
+#[delete("/api/tasks/{id}/reminders/{reminder_id}")]
+async fn delete_reminder(
+    req: HttpRequest,
+    path: web::Path<(i64, i64)>,
+    data: web::Data<AppState>,
+) -> Result<impl Responder, AppError> {
+    ensure_auth(&req, &data).await?;
+    let (task_id, reminder_id) = path.into_inner();
+    let res = db_timed(&req, sqlx::query("DELETE FROM reminders WHERE id = ? AND task_id = ?")
+        .bind(reminder_id)
+        .bind(task_id)
+        .execute(&data.pool)).await
+        .map_err(|e| AppError::Internal(e.to_string()))?;
+    if res.rows_affected() == 0 {
+        return Err(AppError::NotFound);
+    }
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Returns unsent reminders whose time has passed and marks them sent in the same statement,
+/// so each reminder is handed out once even with several workers polling.
+/// Because it consumes reminders, it is not covered by `READ_ONLY_WITHOUT_JWT`.
+#[get("/api/reminders/due")]
+async fn due_reminders(req: HttpRequest, data: web::Data<AppState>) -> Result<impl Responder, AppError> {
+    if data.jwt_enabled && req.headers().get("x-api-key").is_none() {
+        bearer_subject(&req, &data).await?;
+    } else {
+        ensure_auth(&req, &data).await?;
+    }
+    let rows = db_timed(&req, sqlx::query(&format!(
+        "UPDATE reminders SET sent_at = datetime('now') \
+         WHERE sent_at IS NULL AND remind_at <= datetime('now') RETURNING {}",
+        REMINDER_COLUMNS
+    ))
+    .fetch_all(&data.pool)).await
+    .map_err(|e| AppError::Internal(e.to_string()))?;
+    let mut reminders: Vec<Reminder> = rows.iter().map(Reminder::from_row).collect();
+    reminders.sort_by(|a, b| a.remind_at.cmp(&b.remind_at).then(a.id.cmp(&b.id)));
+    Ok(HttpResponse::Ok().json(reminders))
+}
+
+// ---------- List cache ----------
+
+/// TTL cache of `GET /api/tasks` bodies keyed by the caller's subject and the raw query string
+/// (`LIST_CACHE_TTL_SECS`), so one user's cached list is never served to another. Every task mutation
+/// goes through `notify_change`, which clears the whole cache.
+struct ListCache {
+    ttl: Duration,
+    entries: Mutex<HashMap<String, (Instant, String)>>,
+    /// Bumped on every clear, so a list read before a mutation is not stored after it.
+    generation: AtomicU64,
+}
+
+impl ListCache {
+    fn new(ttl: Duration) -> Self {
+        ListCache { ttl, entries: Mutex::new(HashMap::new()), generation: AtomicU64::new(0) }
+    }
+
+    fn get(&self, key: &str) -> Option<String> {
+        let entries = self.entries.lock().unwrap();
+        entries.get(key)
+            .filter(|(stored_at, _)| stored_at.elapsed() < self.ttl)
+            .map(|(_, body)| body.clone())
+    }
+
+    fn generation(&self) -> u64 {
+        self.generation.load(Ordering::SeqCst)
+    }
+
+    fn put(&self, key: String, body: String, generation: u64) {
+        let mut entries = self.entries.lock().unwrap();
+        if self.generation() != generation {
+            return;
+        }
+        // Expired entries of other queries are dropped here rather than by a background task.
+        let ttl = self.ttl;
+        entries.retain(|_, (stored_at, _)| stored_at.elapsed() < ttl);
+        entries.insert(key, (Instant::now(), body));
+    }
+
+    fn clear(&self) {
+        let mut entries = self.entries.lock().unwrap();
+        self.generation.fetch_add(1, Ordering::SeqCst);
+        entries.clear();
+    }
+}
+
+// ---------- Double-submit dedup ----------
+
+/// Recent creates per `(actor, title)`, so a repeated `POST /api/tasks` within `DEDUP_WINDOW_MS`
+/// returns the first task instead of creating another. In-memory and per process; entries are
+/// pruned on insert.
+struct RecentCreates {
+    window: Duration,
+    entries: Mutex<HashMap<(String, String), (Instant, i64)>>,
+}
+
+impl RecentCreates {
+    fn new(window: Duration) -> Self {
+        RecentCreates { window, entries: Mutex::new(HashMap::new()) }
+    }
+
+    fn get(&self, actor: &str, title: &str) -> Option<i64> {
+        let entries = self.entries.lock().unwrap();
+        entries.get(&(actor.to_string(), title.to_string()))
+            .filter(|(created_at, _)| created_at.elapsed() < self.window)
+            .map(|(_, id)| *id)
+    }
+
+    fn put(&self, actor: &str, title: &str, id: i64) {
+        let mut entries = self.entries.lock().unwrap();
+        let window = self.window;
+        entries.retain(|_, (created_at, _)| created_at.elapsed() < window);
+        entries.insert((actor.to_string(), title.to_string()), (Instant::now(), id));
+    }
+}
+
+// ---------- Write-behind ----------
+
+/// A create accepted in `WRITE_BEHIND` mode but not yet written. The provisional id is stored as the
+/// task's `client_id`, so clients can correlate it with the row once it lands.
+struct PendingTask {
+    title: String,
+    progress: i64,
+    estimate_minutes: Option<i64>,
+    expires_at: Option<String>,
+    actor: String,
+    provisional_id: String,
+}
+
+enum WriteBehindMsg {
+    Task(PendingTask),
+    /// Write everything buffered, then acknowledge (used on shutdown).
+    Flush(oneshot::Sender<()>),
+}
+
+/// `now + ttl_seconds` in the stored timestamp format.
+fn expires_at_from_ttl(ttl_seconds: i64) -> Result<String, AppError> {
+    chrono::Duration::try_seconds(ttl_seconds)
+        .and_then(|ttl| Utc::now().checked_add_signed(ttl))
+        .map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string())
+        .ok_or_else(|| field_error("ttl_seconds", "range", "ttl_seconds is too large"))
+}
+
+/// Deletes tasks whose `expires_at` has passed every `interval`, recording a `deleted` change for
+/// each so pollers and delta sync see them go.
+async fn run_expiry_sweeper(data: web::Data<AppState>, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        let result: Result<(Vec<i64>, Option<i64>), AppError> = async {
+            let mut tx = data.pool.begin().await.map_err(|e| AppError::Internal(e.to_string()))?;
+            let ids: Vec<i64> = sqlx::query_scalar(
+                "DELETE FROM tasks WHERE expires_at IS NOT NULL AND expires_at <= datetime('now') RETURNING id"
+            )
+            .fetch_all(&mut *tx).await
+            .map_err(|e| AppError::Internal(e.to_string()))?;
+            let mut last_change = None;
+            for id in &ids {
+                last_change = Some(append_change(&mut tx, *id, "deleted").await?);
+            }
+            tx.commit().await.map_err(|e| AppError::Internal(e.to_string()))?;
+            Ok((ids, last_change))
+        }.await;
+        match result {
+            Ok((ids, last_change)) => {
+                if let Some(change_id) = last_change {
+                    notify_change(&data, change_id);
+                    log::info!("expiry: deleted {} expired tasks", ids.len());
+                } else {
+                    log::debug!("expiry: no expired tasks");
+                }
+            }
+            Err(e) => log::error!("expiry: sweep failed: {}", e),
+        }
+    }
+}
+
+/// Single background writer: buffers creates and inserts them in one transaction every
+/// `flush_interval` or once `max_batch` are pending, whichever comes first.
+async fn run_write_behind(
+    data: web::Data<AppState>,
+    mut rx: mpsc::Receiver<WriteBehindMsg>,
+    flush_interval: Duration,
+    max_batch: usize,
+) {
+    let mut batch: Vec<PendingTask> = Vec::new();
+    let mut interval = tokio::time::interval(flush_interval);
+    loop {
+        tokio::select! {
+            msg = rx.recv() => match msg {
+                Some(WriteBehindMsg::Task(task)) => {
+                    batch.push(task);
+                    if batch.len() >= max_batch {
+                        flush_pending(&data, &mut batch).await;
+                    }
+                }
+                Some(WriteBehindMsg::Flush(ack)) => {
+                    flush_pending(&data, &mut batch).await;
+                    let _ = ack.send(());
+                }
+                None => {
+                    flush_pending(&data, &mut batch).await;
+                    return;
+                }
+            },
+            _ = interval.tick() => flush_pending(&data, &mut batch).await,
+        }
+    }
+}
+
+async fn flush_pending(data: &AppState, batch: &mut Vec<PendingTask>) {
+    if batch.is_empty() {
+        return;
+    }
+    let pending = std::mem::take(batch);
+    // Each row gets its own savepoint, so one bad row (e.g. a duplicate under ENFORCE_UNIQUE_TITLES)
+    // is dropped on its own instead of taking the whole batch with it.
+    let result: Result<(usize, Option<i64>), AppError> = async {
+        let mut tx = data.pool.begin().await.map_err(|e| AppError::Internal(e.to_string()))?;
+        let (mut written, mut last_change) = (0, None);
+        for task in &pending {
+            let mut savepoint = (*tx).begin().await.map_err(|e| AppError::Internal(e.to_string()))?;
+            match insert_pending(&mut savepoint, data, task).await {
+                Ok(change_id) => {
+                    savepoint.commit().await.map_err(|e| AppError::Internal(e.to_string()))?;
+                    written += 1;
+                    last_change = Some(change_id);
+                }
+                Err(e) => {
+                    savepoint.rollback().await.map_err(|e| AppError::Internal(e.to_string()))?;
+                    log::error!("write-behind: dropping task {}: {}", task.provisional_id, e);
+                }
+            }
+        }
+        tx.commit().await.map_err(|e| AppError::Internal(e.to_string()))?;
+        Ok((written, last_change))
+    }.await;
+    match result {
+        Ok((written, last_change)) => {
+            if let Some(change_id) = last_change {
+                notify_change(data, change_id);
+            }
+            log::debug!("write-behind: flushed {} of {} tasks", written, pending.len());
+        }
+        // Accepted creates are lost here; that is the durability trade-off of WRITE_BEHIND.
+        Err(e) => log::error!("write-behind: dropping batch of {} tasks: {}", pending.len(), e),
+    }
+}
+
+/// Inserts one buffered create with its change-log entry; returns the change id.
+async fn insert_pending(conn: &mut SqliteConnection, data: &AppState, task: &PendingTask) -> Result<i64, AppError> {
+    let id: i64 = sqlx::query_scalar(&format!(
+        "INSERT INTO tasks (title, completed, progress, estimate_minutes, expires_at, created_by, updated_by, client_id, position) \
+         VALUES (?, ?, ?, ?, ?, ?, ?, ?, {}) RETURNING id",
+        next_position_sql()
+    ))
+    .bind(encrypt_field(data, &task.title)?)
+    .bind(task.progress == 100)
+    .bind(task.progress)
+    .bind(task.estimate_minutes)
+    .bind(&task.expires_at)
+    .bind(&task.actor)
+    .bind(&task.actor)
+    .bind(&task.provisional_id)
+    .fetch_one(&mut *conn).await
+    .map_err(task_write_error)?;
+    append_change(conn, id, "created").await
+}
+
+// ---------- Change feed ----------
+
+#[derive(Serialize)]
+struct TaskChange {
+    change_id: i64,
+    #[serde(serialize_with = "serialize_id")]
+    task_id: i64,
+    op: String,
+    changed_at: String,
+}
+
+/// Appends to the `task_changes` log, inside the transaction that made the change; call
+/// `notify_change` after committing to wake long-polling clients.
+async fn append_change(conn: &mut SqliteConnection, task_id: i64, op: &str) -> Result<i64, AppError> {
+    sqlx::query_scalar("INSERT INTO task_changes (task_id, op) VALUES (?, ?) RETURNING id")
+        .bind(task_id)
+        .bind(op)
+        .fetch_one(conn).await
+        .map_err(|e| AppError::Internal(e.to_string()))
+}
+
+fn notify_change(data: &AppState, change_id: i64) {
+    if let Some(cache) = &data.list_cache {
+        cache.clear();
+    }
+    // No receivers just means nobody is polling right now.
+    let _ = data.changes.send(change_id);
+}
+
+async fn changes_since(pool: &SqlitePool, since: i64) -> Result<Vec<TaskChange>, AppError> {
+    let rows = sqlx::query("SELECT id, task_id, op, changed_at FROM task_changes WHERE id > ? ORDER BY id ASC LIMIT 500")
+        .bind(since)
+        .fetch_all(pool).await
+        .map_err(|e| AppError::Internal(e.to_string()))?;
+    Ok(rows.into_iter().map(|rec| TaskChange {
+        change_id: rec.get::<i64, _>("id"),
+        task_id: rec.get::<i64, _>("task_id"),
+        op: rec.get::<String, _>("op"),
+        changed_at: rec.get::<String, _>("changed_at"),
+    }).collect())
+}
+
+#[derive(Deserialize)]
+struct PollQuery {
+    since: Option<i64>,
+}
+
+/// Long-polling alternative to WebSockets/SSE: returns changes newer than `since` right away,
+/// otherwise waits up to `POLL_TIMEOUT_SECS` for one before returning an empty list.
+/// Clients resume by passing the returned `change_id` as the next `since`.
+#[get("/api/tasks/poll")]
+async fn poll_changes(query: web::Query<PollQuery>, data: web::Data<AppState>) -> Result<impl Responder, AppError> {
+    let since = query.since.unwrap_or(0);
+    // Subscribe before the first read so a change landing in between is not missed.
+    let mut rx = data.changes.subscribe();
+    let mut changes = changes_since(&data.pool, since).await?;
+    if changes.is_empty() {
+        // Lagged/closed receivers and timeouts all fall through to a re-read.
+        let _ = tokio::time::timeout(data.poll_timeout, rx.recv()).await;
+        changes = changes_since(&data.pool, since).await?;
+    }
+    let change_id = changes.last().map(|c| c.change_id).unwrap_or(since);
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "change_id": change_id,
+        "changes": changes,
+    })))
+}
+
+// ---------- Admin ----------
+
+async fn database_size_bytes(pool: &SqlitePool) -> Result<i64, AppError> {
+    let page_count: i64 = sqlx::query_scalar("PRAGMA page_count").fetch_one(pool).await
+        .map_err(|e| AppError::Internal(e.to_string()))?;
+    let page_size: i64 = sqlx::query_scalar("PRAGMA page_size").fetch_one(pool).await
+        .map_err(|e| AppError::Internal(e.to_string()))?;
+    Ok(page_count * page_size)
+}
+
+/// Clears the in-progress flag even if the vacuum fails or the request is dropped.
+struct VacuumGuard<'a>(&'a AtomicBool);
+
+impl Drop for VacuumGuard<'_> {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::SeqCst);
+    }
+}
+
+#[post("/api/admin/vacuum")]
+async fn vacuum(req: HttpRequest, data: web::Data<AppState>) -> Result<impl Responder, AppError> {
+    ensure_admin(&req, &data).await?;
+    if data.vacuum_in_progress.compare_exchange(false, true, Ordering::SeqCst, Ordering::SeqCst).is_err() {
+        return Err(AppError::Conflict("vacuum already in progress".into()));
+    }
+    let _guard = VacuumGuard(&data.vacuum_in_progress);
+
+    let before = database_size_bytes(&data.pool).await?;
+    sqlx::query("VACUUM").execute(&data.pool).await
+        .map_err(|e| AppError::Internal(e.to_string()))?;
+    let after = database_size_bytes(&data.pool).await?;
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "before_bytes": before,
+        "after_bytes": after,
+        "reclaimed_bytes": before - after,
+    })))
+}
+
+#[derive(Serialize)]
+struct UserUsage {
+    user: String,
+    task_count: i64,
+    completed_count: i64,
+    last_task_created_at: String,
+}
+
+/// Usage per subject that has created tasks, busiest first. Users are not stored, so subjects
+/// without any task (and last-login times) are not known here.
+#[get("/api/admin/users")]
+async fn list_users(
+    req: HttpRequest,
+    query: web::Query<PageQuery>,
+    data: web::Data<AppState>,
+) -> Result<impl Responder, AppError> {
+    ensure_admin(&req, &data).await?;
+    let (limit, offset) = query.resolve()?;
+    let rows = sqlx::query(
+        "SELECT created_by, COUNT(*) AS task_count, SUM(completed) AS completed_count, MAX(created_at) AS last_task_created_at \
+         FROM tasks GROUP BY created_by ORDER BY task_count DESC, created_by ASC LIMIT ? OFFSET ?"
+    )
+    .bind(limit)
+    .bind(offset)
+    .fetch_all(&data.pool).await
+    .map_err(|e| AppError::Internal(e.to_string()))?;
+    let total: i64 = sqlx::query_scalar("SELECT COUNT(DISTINCT created_by) FROM tasks")
+        .fetch_one(&data.pool).await
+        .map_err(|e| AppError::Internal(e.to_string()))?;
+    Ok(HttpResponse::Ok().json(Page {
+        items: rows.iter().map(|r| UserUsage {
+            user: r.get::<String, _>("created_by"),
+            task_count: r.get::<i64, _>("task_count"),
+            completed_count: r.get::<i64, _>("completed_count"),
+            last_task_created_at: r.get::<String, _>("last_task_created_at"),
+        }).collect(),
+        total,
+        limit,
+        offset,
+    }))
+}
+
+#[derive(Deserialize, Validate)]
+struct CloneTasks {
+    #[validate(length(min = 1, message = "from_user must not be empty"))]
+    from_user: String,
+    #[validate(length(min = 1, message = "to_user must not be empty"))]
+    to_user: String,
+}
+
+/// Copies `from_user`'s open tasks to `to_user` (e.g. a template set for a new team member) in one
+/// transaction, keeping their order and appending them after every existing task. Copies belong to
+/// `to_user` (`created_by`); completed tasks, attachments, comments and client ids are not copied.
+/// Users are not stored, so both must appear in the task attribution data (404 otherwise): `from_user`
+/// as a creator, `to_user` as a creator or updater.
+#[post("/api/admin/clone-tasks")]
+async fn clone_tasks(
+    req: HttpRequest,
+    data: web::Data<AppState>,
+    payload: web::Json<CloneTasks>,
+) -> Result<impl Responder, AppError> {
+    let admin = ensure_admin(&req, &data).await?;
+    payload.validate().map_err(AppError::Validation)?;
+    if payload.from_user == payload.to_user {
+        return Err(AppError::BadRequest("from_user and to_user must differ".into()));
+    }
+
+    let mut tx = data.pool.begin().await.map_err(|e| AppError::Internal(e.to_string()))?;
+    let from_known: Option<i64> = sqlx::query_scalar("SELECT 1 FROM tasks WHERE created_by = ? LIMIT 1")
+        .bind(&payload.from_user)
+        .fetch_optional(&mut *tx).await
+        .map_err(|e| AppError::Internal(e.to_string()))?;
+    let to_known: Option<i64> = sqlx::query_scalar("SELECT 1 FROM tasks WHERE created_by = ? OR updated_by = ? LIMIT 1")
+        .bind(&payload.to_user)
+        .bind(&payload.to_user)
+        .fetch_optional(&mut *tx).await
+        .map_err(|e| AppError::Internal(e.to_string()))?;
+    if from_known.is_none() || to_known.is_none() {
+        return Err(AppError::NotFound);
+    }
+    let last_position: f64 = sqlx::query_scalar("SELECT COALESCE(MAX(position), 0) FROM tasks")
+        .fetch_one(&mut *tx).await
+        .map_err(|e| AppError::Internal(e.to_string()))?;
+    let ids: Vec<i64> = sqlx::query_scalar(
+        "INSERT INTO tasks (title, completed, progress, estimate_minutes, expires_at, created_by, updated_by, position) \
+         SELECT title, 0, progress, estimate_minutes, expires_at, ?, ?, ? + ? * ROW_NUMBER() OVER (ORDER BY position, id) \
+         FROM tasks WHERE created_by = ? AND completed = 0 ORDER BY position, id RETURNING id"
+    )
+    .bind(&payload.to_user)
+    .bind(&admin)
+    .bind(last_position)
+    .bind(POSITION_GAP)
+    .bind(&payload.from_user)
+    .fetch_all(&mut *tx).await
+    .map_err(task_write_error)?;
+    let mut last_change = None;
+    for id in &ids {
+        last_change = Some(append_change(&mut tx, *id, "created").await?);
+    }
+    tx.commit().await.map_err(|e| AppError::Internal(e.to_string()))?;
+    if let Some(change_id) = last_change {
+        notify_change(&data, change_id);
+    }
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "from_user": payload.from_user,
+        "to_user": payload.to_user,
+        "copied": ids.len(),
+    })))
+}
+
+// ---------- Backup ----------
+
+/// Tables included in a backup, parents before children so a restore can insert them in this order.
+const BACKUP_TABLES: &[&str] = &[
+    "tasks", "task_changes", "attachments", "comments", "reminders", "task_dependencies", "api_keys", "token_revocations",
+];
+
+const BACKUP_FORMAT: &str = "rust-actix-tasks-backup";
+
+/// The newest migration this binary knows; backups record it and restores require it to match.
+fn schema_version() -> i64 {
+    MIGRATIONS.iter().map(|(version, _)| *version).max().unwrap_or(0)
+}
+
+/// A `SELECT json_object(...)` over every column of `table`, so rows keep SQLite's own types.
+async fn backup_select(conn: &mut SqliteConnection, table: &str) -> Result<String, AppError> {
+    let columns: Vec<String> = sqlx::query_scalar("SELECT name FROM pragma_table_info(?) ORDER BY cid")
+        .bind(table)
+        .fetch_all(conn).await
+        .map_err(|e| AppError::Internal(e.to_string()))?;
+    let pairs = columns.iter().map(|c| format!("'{0}', \"{0}\"", c)).collect::<Vec<_>>().join(", ");
+    Ok(format!("SELECT json_object({}) FROM \"{}\" ORDER BY rowid", pairs, table))
+}
+
+/// Writes the whole backup document to `out`, reading every table in one transaction so the
+/// snapshot is consistent.
+async fn write_backup(data: &AppState, out: &mpsc::Sender<Result<web::Bytes, AppError>>) -> Result<(), AppError> {
+    let send = |chunk: String| async move {
+        out.send(Ok(web::Bytes::from(chunk))).await.map_err(|_| AppError::Internal("backup client went away".into()))
+    };
+    let mut tx = data.pool.begin().await.map_err(|e| AppError::Internal(e.to_string()))?;
+    send(format!(
+        "{{\"format\":{},\"schema_version\":{},\"created_at\":{},\"tables\":{{",
+        serde_json::Value::from(BACKUP_FORMAT), schema_version(), serde_json::Value::from(Utc::now().to_rfc3339()),
+    )).await?;
+    for (i, table) in BACKUP_TABLES.iter().enumerate() {
+        let sql = backup_select(&mut tx, table).await?;
+        send(format!("{}\"{}\":[", if i == 0 { "" } else { "," }, table)).await?;
+        let mut rows = sqlx::query_scalar::<_, String>(&sql).fetch(&mut *tx);
+        let mut first = true;
+        while let Some(row) = rows.next().await {
+            let row = row.map_err(|e| AppError::Internal(e.to_string()))?;
+            send(if first { row } else { format!(",{}", row) }).await?;
+            first = false;
+        }
+        send("]".into()).await?;
+    }
+    send("}}".into()).await
+}
+
+/// Streams every table as one JSON document:
+/// `{"format":...,"schema_version":N,"created_at":...,"tables":{"tasks":[...],...}}`. Rows are the raw
+/// stored values, so encrypted titles stay encrypted and API keys keep only their hashes. If reading
+/// fails midway the connection is cut, leaving a truncated (invalid) document rather than a partial one
+/// that looks complete.
+#[get("/api/admin/backup")]
+async fn backup(req: HttpRequest, data: web::Data<AppState>) -> Result<impl Responder, AppError> {
+    ensure_admin(&req, &data).await?;
+    let (tx, rx) = mpsc::channel::<Result<web::Bytes, AppError>>(64);
+    let data = data.clone();
+    tokio::spawn(async move {
+        if let Err(e) = write_backup(&data, &tx).await {
+            let _ = tx.send(Err(e)).await;
+        }
+    });
+
+    let body = futures_util::stream::unfold(rx, |mut rx| async move { rx.recv().await.map(|item| (item, rx)) });
+    Ok(HttpResponse::Ok().content_type("application/json").streaming(body))
+}
+
+#[derive(Deserialize)]
+struct BackupDocument {
+    format: String,
+    schema_version: i64,
+    tables: HashMap<String, Vec<serde_json::Map<String, serde_json::Value>>>,
+}
+
+#[derive(Deserialize)]
+struct RestoreQuery {
+    /// `merge` (default) or `replace`.
+    mode: Option<String>,
+}
+
+/// Columns holding a task id, remapped to the newly assigned ids when merging.
+const TASK_REF_COLUMNS: &[&str] = &["task_id", "blocked_by_id"];
+
+fn push_json_bind(qb: &mut QueryBuilder<'_, Sqlite>, column: &str, value: serde_json::Value) -> Result<(), AppError> {
+    match value {
+        serde_json::Value::Null => { qb.push_bind(None::<i64>); }
+        serde_json::Value::Bool(b) => { qb.push_bind(b); }
+        serde_json::Value::Number(n) => match n.as_i64() {
+            Some(i) => { qb.push_bind(i); }
+            None => { qb.push_bind(n.as_f64()); }
+        },
+        serde_json::Value::String(text) => { qb.push_bind(text); }
+        _ => return Err(AppError::BadRequest(format!("column '{}' must be a scalar value", column))),
+    }
+    Ok(())
+}
+
+/// Loads a document produced by `/api/admin/backup` in one transaction, so a failed restore changes
+/// nothing. `replace` empties every backed-up table first and keeps the backup's ids. `merge` keeps
+/// existing data and inserts backup rows under new ids, remapping task references; rows that would
+/// violate a unique constraint (client ids, API key hashes, revocations) are skipped, together with
+/// rows that reference a skipped task. Registered in `build_app` with its own `MAX_RESTORE_BYTES` body
+/// limit, since a backup is usually far larger than any other request.
+async fn restore(
+    req: HttpRequest,
+    query: web::Query<RestoreQuery>,
+    data: web::Data<AppState>,
+    doc: web::Json<BackupDocument>,
+) -> Result<impl Responder, AppError> {
+    ensure_admin(&req, &data).await?;
+    let replace = match query.mode.as_deref().unwrap_or("merge") {
+        "replace" => true,
+        "merge" => false,
+        other => return Err(AppError::BadRequest(format!("invalid mode '{}' (expected merge or replace)", other))),
+    };
+    let doc = doc.into_inner();
+    if doc.format != BACKUP_FORMAT {
+        return Err(AppError::BadRequest(format!("not a backup document (format '{}')", doc.format)));
+    }
+    if doc.schema_version != schema_version() {
+        return Err(AppError::BadRequest(format!(
+            "backup schema version {} does not match server schema version {}", doc.schema_version, schema_version()
+        )));
+    }
+    if let Some(unknown) = doc.tables.keys().find(|t| !BACKUP_TABLES.contains(&t.as_str())) {
+        return Err(AppError::BadRequest(format!("unknown table '{}' in backup", unknown)));
+    }
+
+    let mut tx = data.pool.begin().await.map_err(|e| AppError::Internal(e.to_string()))?;
+    if replace {
+        for table in BACKUP_TABLES.iter().rev() {
+            sqlx::query(&format!("DELETE FROM \"{}\"", table))
+                .execute(&mut *tx).await
+                .map_err(|e| AppError::Internal(e.to_string()))?;
+        }
+    }
+
+    let mut task_ids: HashMap<i64, i64> = HashMap::new();
+    let mut restored = serde_json::Map::new();
+    let mut skipped = serde_json::Map::new();
+    for table in BACKUP_TABLES {
+        let columns: Vec<String> = sqlx::query_scalar("SELECT name FROM pragma_table_info(?)")
+            .bind(table)
+            .fetch_all(&mut *tx).await
+            .map_err(|e| AppError::Internal(e.to_string()))?;
+        let (mut restored_rows, mut skipped_rows) = (0u64, 0u64);
+        'rows: for row in doc.tables.get(*table).into_iter().flatten() {
+            let old_id = row.get("id").and_then(|v| v.as_i64());
+            let mut values = Vec::new();
+            for (column, value) in row {
+                if !columns.contains(column) {
+                    return Err(AppError::BadRequest(format!("unknown column '{}' in table '{}'", column, table)));
+                }
+                if !replace && column == "id" {
+                    continue;
+                }
+                let value = if !replace && TASK_REF_COLUMNS.contains(&column.as_str()) {
+                    match value.as_i64().and_then(|old| task_ids.get(&old)) {
+                        Some(new) => serde_json::Value::from(*new),
+                        None => {
+                            skipped_rows += 1;
+                            continue 'rows;
+                        }
+                    }
+                } else {
+                    value.clone()
+                };
+                values.push((column.as_str(), value));
+            }
+
+            let mut qb = QueryBuilder::new(format!(
+                "INSERT INTO \"{}\" ({}) VALUES (",
+                table,
+                values.iter().map(|(c, _)| format!("\"{}\"", c)).collect::<Vec<_>>().join(", "),
+            ));
+            for (i, (column, value)) in values.into_iter().enumerate() {
+                if i > 0 {
+                    qb.push(", ");
+                }
+                push_json_bind(&mut qb, column, value)?;
+            }
+            qb.push(")");
+            // Only uniqueness conflicts are skipped; NOT NULL and CHECK failures still abort the restore.
+            if !replace {
+                qb.push(" ON CONFLICT DO NOTHING");
+            }
+            let restore_error = |e: sqlx::Error| AppError::BadRequest(format!("restoring table '{}' failed: {}", table, e));
+            let inserted = if *table == "tasks" {
+                qb.push(" RETURNING id");
+                let new_id: Option<i64> = qb.build_query_scalar().fetch_optional(&mut *tx).await.map_err(restore_error)?;
+                if let (Some(old), Some(new)) = (old_id, new_id) {
+                    task_ids.insert(old, new);
+                }
+                new_id.is_some()
+            } else {
+                qb.build().execute(&mut *tx).await.map_err(restore_error)?.rows_affected() > 0
+            };
+            if inserted { restored_rows += 1 } else { skipped_rows += 1 }
+        }
+        restored.insert(table.to_string(), restored_rows.into());
+        skipped.insert(table.to_string(), skipped_rows.into());
+    }
+    let last_change: i64 = sqlx::query_scalar("SELECT COALESCE(MAX(id), 0) FROM task_changes")
+        .fetch_one(&mut *tx).await
+        .map_err(|e| AppError::Internal(e.to_string()))?;
+    tx.commit().await.map_err(|e| AppError::Internal(e.to_string()))?;
+    notify_change(&data, last_change);
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({
+        "mode": if replace { "replace" } else { "merge" },
+        "restored": restored,
+        "skipped": skipped,
+    })))
+}
+
+async fn table_counts(conn: &mut SqliteConnection) -> Result<serde_json::Map<String, serde_json::Value>, AppError> {
+    let mut counts = serde_json::Map::new();
+    for table in BACKUP_TABLES {
+        let count: i64 = sqlx::query_scalar(&format!("SELECT COUNT(*) FROM \"{}\"", table))
+            .fetch_one(&mut *conn).await
+            .map_err(|e| AppError::Internal(e.to_string()))?;
+        counts.insert(table.to_string(), count.into());
+    }
+    Ok(counts)
+}
+
+/// Empties every data table (the ones backups cover; migrations are kept) in one transaction and
+/// returns per-table row counts from before and after. For tests and demos only: 403 unless
+/// `ALLOW_RESET` is set, and with JWT enabled the caller must also be an admin.
+#[post("/api/admin/reset")]
+async fn reset(req: HttpRequest, data: web::Data<AppState>) -> Result<impl Responder, AppError> {
+    if !data.allow_reset {
+        return Err(AppError::Forbidden);
+    }
+    if data.jwt_enabled {
+        ensure_admin(&req, &data).await?;
+    }
+
+    let mut tx = data.pool.begin().await.map_err(|e| AppError::Internal(e.to_string()))?;
+    let before = table_counts(&mut tx).await?;
+    for table in BACKUP_TABLES.iter().rev() {
+        sqlx::query(&format!("DELETE FROM \"{}\"", table))
+            .execute(&mut *tx).await
+            .map_err(|e| AppError::Internal(e.to_string()))?;
+    }
+    let after = table_counts(&mut tx).await?;
+    tx.commit().await.map_err(|e| AppError::Internal(e.to_string()))?;
+    notify_change(&data, 0);
+    log::warn!("all data was reset via /api/admin/reset");
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "before": before, "after": after })))
+}
+
+// ---------- Server-Timing ----------
+
+/// Database time accumulated by `db_timed` for the current request.
+#[derive(Clone, Default)]
+struct DbTiming(Rc<Cell<Duration>>);
+
+/// Awaits `fut`, adding its duration to the request's `db` Server-Timing metric when enabled and
+/// logging it at warn level (target `slow_query`) when it exceeds `SLOW_QUERY_MS`. The log line carries
+/// the method and path, which include the task id; no bound values are logged.
+async fn db_timed<F: Future>(req: &HttpRequest, fut: F) -> F::Output {
+    let start = Instant::now();
+    let out = fut.await;
+    let elapsed = start.elapsed();
+    if let Some(timing) = req.extensions().get::<DbTiming>() {
+        timing.0.set(timing.0.get() + elapsed);
+    }
+    let threshold = req.app_data::<web::Data<AppState>>().and_then(|d| d.slow_query);
+    if threshold.is_some_and(|t| elapsed >= t) {
+        log::warn!(target: "slow_query", "{} {}: DB operation took {:.1}ms", req.method(), req.path(), elapsed.as_secs_f64() * 1000.0);
+    }
+    out
+}
+
+/// With `SERVER_TIMING=1`, adds `Server-Timing: db;dur=.., total;dur=..` (milliseconds) to every response.
+async fn add_server_timing(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let enabled = req.app_data::<web::Data<AppState>>().is_some_and(|d| d.server_timing);
+    if !enabled {
+        return next.call(req).await;
+    }
+    let timing = DbTiming::default();
+    req.extensions_mut().insert(timing.clone());
+    let start = Instant::now();
+    let mut res = next.call(req).await?;
+    let value = format!(
+        "db;dur={:.1}, total;dur={:.1}",
+        timing.0.get().as_secs_f64() * 1000.0,
+        start.elapsed().as_secs_f64() * 1000.0
+    );
+    if let Ok(value) = HeaderValue::from_str(&value) {
+        res.headers_mut().insert(HeaderName::from_static("server-timing"), value);
+    }
+    Ok(res)
+}
+
+// ---------- Rate limiting ----------
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+struct RateDecision {
+    allowed: bool,
+    limit: u64,
+    remaining: u64,
+    /// Seconds until the bucket is full again.
+    reset_secs: u64,
+    /// Seconds until the next request would be allowed (0 if allowed now).
+    retry_after_secs: u64,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum RateLimitMode {
+    /// Count and emit headers, but only log clients that would have been limited.
+    Monitor,
+    Enforce,
+}
+
+impl FromStr for RateLimitMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "monitor" => Ok(RateLimitMode::Monitor),
+            "enforce" => Ok(RateLimitMode::Enforce),
+            other => Err(format!("invalid RATE_LIMIT_MODE '{}' (expected monitor or enforce)", other)),
+        }
+    }
+}
+
+/// Token bucket per client key: `capacity` requests, refilled evenly over `window`.
+struct RateLimiter {
+    capacity: f64,
+    window: Duration,
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl RateLimiter {
+    fn new(capacity: u64, window: Duration) -> RateLimiter {
+        RateLimiter { capacity: capacity as f64, window, buckets: Mutex::new(HashMap::new()) }
+    }
+
+    fn refill_per_sec(&self) -> f64 {
+        self.capacity / self.window.as_secs_f64().max(0.001)
+    }
+
+    fn check(&self, key: &str) -> RateDecision {
+        let now = Instant::now();
+        let rate = self.refill_per_sec();
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+        let bucket = buckets.entry(key.to_string()).or_insert(Bucket { tokens: self.capacity, updated: now });
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * rate).min(self.capacity);
+        bucket.updated = now;
+        let allowed = bucket.tokens >= 1.0;
+        if allowed {
+            bucket.tokens -= 1.0;
+        }
+        RateDecision {
+            allowed,
+            limit: self.capacity as u64,
+            remaining: bucket.tokens.floor() as u64,
+            reset_secs: ((self.capacity - bucket.tokens) / rate).ceil() as u64,
+            retry_after_secs: if allowed { 0 } else { ((1.0 - bucket.tokens) / rate).ceil() as u64 },
+        }
+    }
+
+    /// Drops buckets idle long enough to have refilled completely; they are equivalent to new ones.
+    fn cleanup(&self) {
+        let now = Instant::now();
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+        buckets.retain(|_, b| now.duration_since(b.updated) < self.window);
+    }
+}
+
+fn rate_limit_headers(headers: &mut HeaderMap, decision: &RateDecision) {
+    for (name, value) in [
+        ("x-ratelimit-limit", decision.limit),
+        ("x-ratelimit-remaining", decision.remaining),
+        ("x-ratelimit-reset", decision.reset_secs),
+    ] {
+        headers.insert(HeaderName::from_static(name), HeaderValue::from(value));
+    }
+}
+
+/// Global per-IP limit (`GLOBAL_RATE_LIMIT` requests per `GLOBAL_RATE_WINDOW` seconds). `/health*` is exempt.
+/// In `RATE_LIMIT_MODE=monitor` requests are never rejected.
+async fn enforce_global_rate_limit(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, actix_web::Error> {
+    let data = req.app_data::<web::Data<AppState>>().cloned();
+    let Some(limiter) = data.as_ref().and_then(|d| d.global_rate_limiter.as_ref()) else {
+        return next.call(req).await.map(ServiceResponse::map_into_left_body);
+    };
+    if req.path().starts_with("/health") {
+        return next.call(req).await.map(ServiceResponse::map_into_left_body);
+    }
+    let key = req.peer_addr().map(|a| a.ip().to_string()).unwrap_or_else(|| "unknown".into());
+    let decision = limiter.check(&key);
+    if !decision.allowed && data.as_ref().is_some_and(|d| d.rate_limit_mode == RateLimitMode::Monitor) {
+        log::warn!("rate limit (monitor mode): {} would have been limited on {}", key, req.path());
+    } else if !decision.allowed {
+        let mut res = HttpResponse::TooManyRequests().json(serde_json::json!({ "error": "Too Many Requests" }));
+        rate_limit_headers(res.headers_mut(), &decision);
+        res.headers_mut().insert(RETRY_AFTER, HeaderValue::from(decision.retry_after_secs));
+        return Ok(req.into_response(res).map_into_right_body());
+    }
+    let mut res = next.call(req).await?;
+    rate_limit_headers(res.headers_mut(), &decision);
+    Ok(res.map_into_left_body())
+}
+
+/// At most `MAX_CONCURRENT_REQUESTS` requests are handled at once; the rest get 503 with `Retry-After`
+/// right away instead of queueing for the database. `/health*` is exempt. The permit is released when
+/// the handler returns, so a streaming body still being sent does not count.
+async fn limit_concurrency(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, actix_web::Error> {
+    let semaphore = req.app_data::<web::Data<AppState>>().and_then(|d| d.request_semaphore.clone());
+    let Some(semaphore) = semaphore.filter(|_| !req.path().starts_with("/health")) else {
+        return next.call(req).await.map(ServiceResponse::map_into_left_body);
+    };
+    let Ok(_permit) = semaphore.try_acquire_owned() else {
+        log::warn!("concurrency limit reached, rejecting {} {}", req.method(), req.path());
+        let mut res = HttpResponse::ServiceUnavailable().json(serde_json::json!({ "error": "Server busy, retry shortly" }));
+        res.headers_mut().insert(RETRY_AFTER, HeaderValue::from(1));
+        return Ok(req.into_response(res).map_into_right_body());
+    };
+    next.call(req).await.map(ServiceResponse::map_into_left_body)
+}
+
+// ---------- Security headers ----------
+
+/// Browser hardening headers added to every response; `None` disables a header.
+struct SecurityHeaders {
+    content_type_options: Option<HeaderValue>,
+    frame_options: Option<HeaderValue>,
+    referrer_policy: Option<HeaderValue>,
+    content_security_policy: Option<HeaderValue>,
+}
+
+impl SecurityHeaders {
+    /// Reads `<var>`, falling back to `default`; `off` (or empty) disables the header.
+    fn header_from_env(var: &str, default: Option<&str>) -> Option<HeaderValue> {
+        let value = env::var(var).ok().or_else(|| default.map(str::to_string))?;
+        if value.is_empty() || value.eq_ignore_ascii_case("off") {
+            return None;
+        }
+        Some(HeaderValue::from_str(&value).unwrap_or_else(|_| panic!("Invalid header value in {}", var)))
+    }
+
+    fn from_env() -> SecurityHeaders {
+        SecurityHeaders {
+            content_type_options: Self::header_from_env("X_CONTENT_TYPE_OPTIONS", Some("nosniff")),
+            frame_options: Self::header_from_env("X_FRAME_OPTIONS", Some("DENY")),
+            referrer_policy: Self::header_from_env("REFERRER_POLICY", Some("no-referrer")),
+            content_security_policy: Self::header_from_env("CONTENT_SECURITY_POLICY", None),
+        }
+    }
+}
+
+/// Adds the configured security headers without overriding any a handler set itself. It wraps all
+/// other middleware, which answer rejections (415, 429, 503) with responses rather than errors so
+/// those get the headers too.
+async fn add_security_headers(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let data = req.app_data::<web::Data<AppState>>().cloned();
+    let mut res = next.call(req).await?;
+    if let Some(data) = data {
+        let config = &data.security_headers;
+        for (name, value) in [
+            (X_CONTENT_TYPE_OPTIONS, &config.content_type_options),
+            (X_FRAME_OPTIONS, &config.frame_options),
+            (REFERRER_POLICY, &config.referrer_policy),
+            (CONTENT_SECURITY_POLICY, &config.content_security_policy),
+        ] {
+            if let Some(value) = value {
+                if !res.headers().contains_key(&name) {
+                    res.headers_mut().insert(name, value.clone());
+                }
+            }
+        }
+    }
+    Ok(res)
+}
+
+// ---------- Request encoding ----------
+
+/// Compressed request bodies (`Content-Encoding: gzip`, also deflate/br/zstd) are decoded by the JSON
+/// extractor, with `MAX_JSON_BYTES` applied to the decompressed size. Anything it can't decode would
+/// otherwise be parsed as-is, so reject it up front.
+async fn reject_unsupported_encoding(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, actix_web::Error> {
+    if let Some(value) = req.headers().get(CONTENT_ENCODING) {
+        let supported = value.to_str().ok().is_some_and(|v| v.parse::<ContentEncoding>().is_ok());
+        if !supported {
+            let err = AppError::UnsupportedMediaType(format!(
+                "unsupported Content-Encoding '{}'", value.to_str().unwrap_or("?")
+            ));
+            return Ok(req.error_response(err).map_into_right_body());
+        }
+    }
+    next.call(req).await.map(ServiceResponse::map_into_left_body)
+}
+
+// ---------- Migrations ----------
+
+/// Versioned migrations, applied in order. Applied versions are recorded in `_migrations`
+/// so that non-idempotent statements (e.g. `ALTER TABLE`) only run once.
+const MIGRATIONS: &[(i64, &str)] = &[
+    (1, "migrations/001_init.sql"),
+    (2, "migrations/002_task_attribution.sql"),
+    (3, "migrations/003_task_changes.sql"),
+    (4, "migrations/004_attachments.sql"),
+    (5, "migrations/005_task_client_id.sql"),
+    (6, "migrations/006_task_position.sql"),
+    (7, "migrations/007_api_keys.sql"),
+    (8, "migrations/008_comments.sql"),
+    (9, "migrations/009_reminders.sql"),
+    (10, "migrations/010_task_progress.sql"),
+    (11, "migrations/011_task_estimate.sql"),
+    (12, "migrations/012_task_changes_changed_at.sql"),
+    (13, "migrations/013_task_dependencies.sql"),
+    (14, "migrations/014_token_revocations.sql"),
+    (15, "migrations/015_task_completed_at.sql"),
+    (16, "migrations/016_task_expires_at.sql"),
+    (17, "migrations/017_pending_tasks_index.sql"),
+    (18, "migrations/018_task_snoozed_until.sql"),
+    (19, "migrations/019_task_completed_at_on_insert.sql"),
+];
+
+const UNIQUE_TITLE_INDEX: &str = "idx_tasks_unique_title";
+
+/// Optional, so it is not part of `MIGRATIONS`: with `ENFORCE_UNIQUE_TITLES` the index is created on
+/// startup, without it any earlier index is dropped. Creating it fails if duplicates already exist.
+async fn apply_unique_title_index(pool: &SqlitePool, enabled: bool) -> Result<(), AppError> {
+    let sql = if enabled {
+        format!("CREATE UNIQUE INDEX IF NOT EXISTS {} ON tasks(created_by, lower(title))", UNIQUE_TITLE_INDEX)
+    } else {
+        format!("DROP INDEX IF EXISTS {}", UNIQUE_TITLE_INDEX)
+    };
+    sqlx::query(&sql).execute(pool).await
+        .map_err(|e| AppError::Internal(format!("Applying {} failed: {}", UNIQUE_TITLE_INDEX, e)))?;
+    Ok(())
+}
+
+/// Applies pending migrations and records a SHA-256 of each file. Already-applied files must still
+/// match their recorded checksum, so a migration edited after the fact stops startup instead of
+/// silently diverging between databases.
+async fn run_migrations(pool: &SqlitePool) -> Result<(), AppError> {
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS _migrations (version INTEGER PRIMARY KEY, applied_at TEXT NOT NULL DEFAULT (datetime('now')))"
+    )
+    .execute(pool).await
+    .map_err(|e| AppError::Internal(e.to_string()))?;
+    // Databases migrated before checksums were recorded lack the column.
+    let has_checksum: bool = sqlx::query_scalar("SELECT COUNT(*) > 0 FROM pragma_table_info('_migrations') WHERE name = 'checksum'")
+        .fetch_one(pool).await
+        .map_err(|e| AppError::Internal(e.to_string()))?;
+    if !has_checksum {
+        sqlx::query("ALTER TABLE _migrations ADD COLUMN checksum TEXT")
+            .execute(pool).await
+            .map_err(|e| AppError::Internal(e.to_string()))?;
+    }
+
+    for (version, path) in MIGRATIONS {
+        let sql = fs::read_to_string(path)
+            .map_err(|e| AppError::Internal(format!("Failed reading migration {}: {}", path, e)))?;
+        let checksum: String = Sha256::digest(sql.as_bytes()).iter().map(|b| format!("{:02x}", b)).collect();
+        let applied: Option<Option<String>> = sqlx::query_scalar("SELECT checksum FROM _migrations WHERE version = ?")
+            .bind(version)
+            .fetch_optional(pool).await
+            .map_err(|e| AppError::Internal(e.to_string()))?;
+        match applied {
+            Some(Some(recorded)) if recorded != checksum => {
+                return Err(AppError::Internal(format!(
+                    "migration {} ({}) was modified after it was applied: recorded checksum {}, file now has {}. \
+                     Restore the original file and add a new migration for the change",
+                    version, path, recorded, checksum
+                )));
+            }
+            Some(Some(_)) => continue,
+            Some(None) => {
+                // Applied before checksums were recorded: trust the current file from now on.
+                sqlx::query("UPDATE _migrations SET checksum = ? WHERE version = ?")
+                    .bind(&checksum)
+                    .bind(version)
+                    .execute(pool).await
+                    .map_err(|e| AppError::Internal(e.to_string()))?;
+                continue;
+            }
+            None => {}
+        }
+        let mut tx = pool.begin().await.map_err(|e| AppError::Internal(e.to_string()))?;
+        sqlx::query(&sql).execute(&mut *tx).await
+            .map_err(|e| AppError::Internal(format!("Migration {} failed: {}", path, e)))?;
+        sqlx::query("INSERT INTO _migrations (version, checksum) VALUES (?, ?)")
+            .bind(version)
+            .bind(&checksum)
+            .execute(&mut *tx).await
+            .map_err(|e| AppError::Internal(e.to_string()))?;
+        tx.commit().await.map_err(|e| AppError::Internal(e.to_string()))?;
+    }
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct AppliedMigration {
+    version: i64,
+    applied_at: String,
+}
+
+/// Compares `_migrations` with the versions this binary knows about. 503 while any are pending,
+/// so CI and orchestrators can wait on it.
+#[get("/health/migrations")]
+async fn migration_status(data: web::Data<AppState>) -> Result<impl Responder, AppError> {
+    let rows = sqlx::query("SELECT version, applied_at FROM _migrations ORDER BY version ASC")
+        .fetch_all(&data.pool).await
+        .map_err(|e| AppError::Internal(e.to_string()))?;
+    let applied: Vec<AppliedMigration> = rows.iter().map(|r| AppliedMigration {
+        version: r.get::<i64, _>("version"),
+        applied_at: r.get::<String, _>("applied_at"),
+    }).collect();
+    let pending: Vec<i64> = MIGRATIONS.iter()
+        .map(|(version, _)| *version)
+        .filter(|v| !applied.iter().any(|a| a.version == *v))
+        .collect();
+    let body = serde_json::json!({
+        "latest_applied": applied.last().map(|a| a.version),
+        "latest_known": MIGRATIONS.last().map(|(version, _)| *version),
+        "applied": applied,
+        "pending": pending,
+    });
+    if pending.is_empty() {
+        Ok(HttpResponse::Ok().json(body))
+    } else {
+        Ok(HttpResponse::ServiceUnavailable().json(body))
+    }
+}
+
+/// Catches migration drift (e.g. a hand-edited database) at startup instead of as confusing
+/// runtime errors: every column in `TASK_COLUMNS` must exist on `tasks`.
+async fn check_task_schema(pool: &SqlitePool) -> Result<(), AppError> {
+    let rows = sqlx::query("PRAGMA table_info(tasks)")
+        .fetch_all(pool).await
+        .map_err(|e| AppError::Internal(e.to_string()))?;
+    let actual: Vec<String> = rows.iter().map(|r| r.get::<String, _>("name")).collect();
+    let expected: Vec<&str> = TASK_COLUMNS.split(", ").collect();
+    let missing: Vec<&str> = expected.iter().copied().filter(|c| !actual.iter().any(|a| a == c)).collect();
+    if !missing.is_empty() {
+        return Err(AppError::Internal(format!(
+            "tasks table is missing columns {:?} (expected {:?}, found {:?})",
+            missing, expected, actual
+        )));
+    }
+    Ok(())
+}
+
+// ---------- Main ----------
+
+// ---------- App ----------
+
+/// Reads every `AppState` setting from the environment (see the README for each variable).
+/// `write_behind` is the queue sender when `WRITE_BEHIND` is on; `main` owns the receiving end.
+fn app_state_from_env(pool: SqlitePool, write_behind: Option<mpsc::Sender<WriteBehindMsg>>) -> AppState {
+    let jwt_secret = env::var("JWT_SECRET").ok();
+    let read_only_without_jwt = env::var("READ_ONLY_WITHOUT_JWT").ok().map(|v| v == "1" || v.to_lowercase() == "true").unwrap_or(true);
+    let jwt_enabled = jwt_secret.is_some();
+    // AUTH_HEADER / AUTH_SCHEME: where the JWT is read from, for gateways that strip `Authorization`
+    let auth_header = HeaderName::from_str(&env::var("AUTH_HEADER").unwrap_or_else(|_| "authorization".into()))
+        .expect("Invalid AUTH_HEADER");
+    let auth_scheme = env::var("AUTH_SCHEME").unwrap_or_else(|_| "Bearer ".into());
+    assert!(!auth_scheme.trim().is_empty(), "AUTH_SCHEME must not be empty");
+    // JWT_ISSUER / JWT_AUDIENCE: put into issued tokens and required when validating
+    let jwt_issuer = env::var("JWT_ISSUER").ok().filter(|v| !v.is_empty());
+    let jwt_audience = env::var("JWT_AUDIENCE").ok().filter(|v| !v.is_empty());
+    let anonymous_subject = env::var("ANONYMOUS_SUBJECT").unwrap_or_else(|_| "anonymous".into());
+    assert!(!anonymous_subject.trim().is_empty(), "ANONYMOUS_SUBJECT must not be empty");
+    // TRIM_TRAILING_SLASH: route `/api/tasks/` like `/api/tasks` (on unless set to false/0)
+    let trim_trailing_slash = env::var("TRIM_TRAILING_SLASH").ok().map(|v| v == "1" || v.to_lowercase() == "true").unwrap_or(true);
+    let strict_json = env::var("STRICT_JSON").ok().map(|v| v == "1" || v.to_lowercase() == "true").unwrap_or(false);
+    let server_timing = env::var("SERVER_TIMING").ok().map(|v| v == "1" || v.to_lowercase() == "true").unwrap_or(false);
+    let max_title_len: usize = env::var("MAX_TITLE_LEN").ok().and_then(|v| v.parse().ok()).unwrap_or(500);
+    let global_rate_limit: u64 = env::var("GLOBAL_RATE_LIMIT").ok().and_then(|v| v.parse().ok()).unwrap_or(0);
+    let global_rate_window: u64 = env::var("GLOBAL_RATE_WINDOW").ok().and_then(|v| v.parse().ok()).unwrap_or(60);
+    let rate_limit_mode: RateLimitMode = env::var("RATE_LIMIT_MODE").unwrap_or_else(|_| "enforce".into())
+        .parse().expect("Invalid RATE_LIMIT_MODE");
+    let max_json_bytes: usize = env::var("MAX_JSON_BYTES").ok().and_then(|v| v.parse().ok()).unwrap_or(2 * 1024 * 1024);
+    let max_restore_bytes: usize = env::var("MAX_RESTORE_BYTES").ok().and_then(|v| v.parse().ok()).unwrap_or(256 * 1024 * 1024);
+    let default_list_completed: CompletedFilter = env::var("DEFAULT_LIST_COMPLETED").unwrap_or_else(|_| "all".into())
+        .parse().expect("Invalid DEFAULT_LIST_COMPLETED");
+    let default_sort: ListSort = env::var("DEFAULT_SORT").unwrap_or_else(|_| "id:desc".into())
+        .parse().expect("Invalid DEFAULT_SORT");
+    let title_control_chars: ControlCharMode = env::var("TITLE_CONTROL_CHARS").unwrap_or_else(|_| "reject".into())
+        .parse().expect("Invalid TITLE_CONTROL_CHARS");
+    let soft_validation: Vec<&'static str> = env::var("SOFT_VALIDATION").unwrap_or_default()
+        .split(',').map(str::trim).filter(|r| !r.is_empty()).map(|r| {
+            SOFT_RULES.iter().copied().find(|known| *known == r).unwrap_or_else(|| {
+                panic!("Invalid SOFT_VALIDATION entry '{}' (expected one of: {})", r, SOFT_RULES.join(", "))
+            })
+        }).collect();
+    let admin_users: Vec<String> = env::var("ADMIN_USERS").unwrap_or_default()
+        .split(',').map(|s| s.trim().to_string()).filter(|s| !s.is_empty()).collect();
+    // USER_EDITABLE_FIELDS: comma-separated task fields non-admins may change (unset = all)
+    let user_editable_fields: Option<Vec<&'static str>> = env::var("USER_EDITABLE_FIELDS").ok().map(|v| {
+        v.split(',').map(str::trim).filter(|f| !f.is_empty()).map(|f| {
+            EDITABLE_FIELDS.iter().copied().find(|known| *known == f).unwrap_or_else(|| {
+                panic!("Invalid USER_EDITABLE_FIELDS entry '{}' (expected one of: {})", f, EDITABLE_FIELDS.join(", "))
+            })
+        }).collect()
+    });
+    let max_attachments_per_task: i64 = env::var("MAX_ATTACHMENTS_PER_TASK").ok().and_then(|v| v.parse().ok()).unwrap_or(20);
+    // ENCRYPTION_KEY: base64-encoded 32-byte key enabling AES-256-GCM for task titles at rest
+    let cipher = env::var("ENCRYPTION_KEY").ok().map(|k| {
+        let key = BASE64.decode(k.trim()).expect("ENCRYPTION_KEY must be base64");
+        Aes256Gcm::new_from_slice(&key).expect("ENCRYPTION_KEY must decode to 32 bytes")
+    });
+    let poll_timeout_secs: u64 = env::var("POLL_TIMEOUT_SECS").ok().and_then(|v| v.parse().ok()).unwrap_or(30);
+
+    // DB operations slower than SLOW_QUERY_MS are logged by `db_timed` (unset or 0 = off). sqlx's own
+    // slow-statement log stays off so each one is reported once.
+    let slow_query = env::var("SLOW_QUERY_MS").ok().and_then(|v| v.parse::<u64>().ok())
+        .filter(|ms| *ms > 0)
+        .map(Duration::from_millis);
+
+    AppState {
+        pool,
+        jwt_enabled,
+        jwt_secret,
+        auth_header,
+        auth_scheme,
+        jwt_issuer,
+        jwt_audience,
+        anonymous_subject,
+        allow_reset: env::var("ALLOW_RESET").ok().map(|v| v == "1" || v.to_lowercase() == "true").unwrap_or(false),
+        strict_json,
+        max_json_bytes,
+        max_restore_bytes,
+        trim_trailing_slash,
+        read_only_without_jwt,
+        changes: broadcast::channel(64).0,
+        poll_timeout: Duration::from_secs(poll_timeout_secs),
+        cipher,
+        max_attachments_per_task,
+        admin_users,
+        user_editable_fields,
+        vacuum_in_progress: AtomicBool::new(false),
+        server_timing,
+        max_title_len,
+        global_rate_limiter: (global_rate_limit > 0)
+            .then(|| RateLimiter::new(global_rate_limit, Duration::from_secs(global_rate_window.max(1)))),
+        request_semaphore: env::var("MAX_CONCURRENT_REQUESTS").ok().and_then(|v| v.parse::<usize>().ok())
+            .filter(|n| *n > 0)
+            .map(|n| Arc::new(Semaphore::new(n))),
+        introspect_rate_limiter: RateLimiter::new(
+            env::var("INTROSPECT_RATE_LIMIT").ok().and_then(|v| v.parse().ok()).unwrap_or(30).max(1),
+            Duration::from_secs(60),
+        ),
+        rate_limit_mode,
+        default_list_completed,
+        default_sort,
+        title_control_chars,
+        soft_validation,
+        security_headers: SecurityHeaders::from_env(),
+        write_behind,
+        envelope_responses: env::var("ENVELOPE_RESPONSES").ok().map(|v| v == "1" || v.to_lowercase() == "true").unwrap_or(false),
+        slow_query,
+        list_cache: env::var("LIST_CACHE_TTL_SECS").ok().and_then(|v| v.parse::<u64>().ok())
+            .filter(|secs| *secs > 0)
+            .map(|secs| ListCache::new(Duration::from_secs(secs))),
+        recent_creates: env::var("DEDUP_WINDOW_MS").ok().and_then(|v| v.parse::<u64>().ok())
+            .filter(|ms| *ms > 0)
+            .map(|ms| RecentCreates::new(Duration::from_millis(ms))),
+        lock_completed_tasks: env::var("LOCK_COMPLETED_TASKS").ok().map(|v| v == "1" || v.to_lowercase() == "true").unwrap_or(false),
+        write_behind_seq: AtomicU64::new(0),
+        started_at: Utc::now().timestamp(),
+    }
+}
+
+/// The application with all middleware, extractor config and routes; `main` serves it and the
+/// tests drive it through `actix_web::test`.
+fn build_app(
+    state: web::Data<AppState>,
+) -> App<impl ServiceFactory<ServiceRequest, Config = (), Response = ServiceResponse<impl MessageBody>, Error = actix_web::Error, InitError = ()>> {
+    let (max_json_bytes, max_restore_bytes, trim_trailing_slash) =
+        (state.max_json_bytes, state.max_restore_bytes, state.trim_trailing_slash);
+    App::new()
+        .wrap(from_fn(reject_unsupported_encoding))
+        .wrap(from_fn(limit_concurrency))
+        .wrap(from_fn(enforce_global_rate_limit))
+        .wrap(Logger::default())
+        .wrap(from_fn(add_server_timing))
+        // Outside the rest, so the other middleware (e.g. the /health exemptions) see the trimmed path
+        .wrap(Condition::new(trim_trailing_slash, NormalizePath::trim()))
+        .wrap(from_fn(add_security_headers))
+        .app_data(state)
+        .app_data(web::JsonConfig::default().limit(max_json_bytes).error_handler(json_error))
+        .app_data(web::PathConfig::default().error_handler(path_error))
+        .service(migration_status)
+        .service(login)
+        .service(introspect_token)
+        .service(create_task)
+        .service(list_tasks)
+        // Literal /api/tasks/... routes must be registered before /api/tasks/{id}
+        .service(poll_changes)
+        .service(task_schema)
+        .service(stream_tasks)
+        .service(count_tasks)
+        .service(task_workload)
+        .service(grouped_tasks)
+        .service(task_activity)
+        .service(get_task)
+        .service(update_task)
+        .service(patch_task)
+        .service(upsert_task_by_client_id)
+        .service(move_task)
+        .service(snooze_task)
+        .service(bulk_toggle_tasks)
+        .service(batch_tasks)
+        .service(delete_task)
+        .service(add_attachment)
+        .service(list_attachments)
+        .service(delete_attachment)
+        .service(add_comment)
+        .service(list_comments)
+        .service(add_dependency)
+        .service(delete_dependency)
+        .service(list_blockers)
+        .service(merge_task)
+        .service(add_reminder)
+        .service(list_reminders)
+        .service(delete_reminder)
+        .service(due_reminders)
+        .service(vacuum)
+        .service(list_users)
+        .service(clone_tasks)
+        .service(backup)
+        .service(
+            web::resource("/api/admin/restore")
+                .app_data(web::JsonConfig::default().limit(max_restore_bytes).error_handler(json_error))
+                .route(web::post().to(restore)),
+        )
+        .service(reset)
+        .service(create_api_key)
+        .service(list_api_keys)
+        .service(revoke_api_key)
+        .service(revoke_user_tokens)
+}
+
+#[actix_web::main]
+async fn main() -> std::io::Result<()> {
+    dotenvy::dotenv().ok();
+    env_logger::init();
+
+    let database_url = env::var("DATABASE_URL").unwrap_or_else(|_| "sqlite://data.db".into());
+    let bind_addr = env::var("BIND_ADDR").unwrap_or_else(|_| "0.0.0.0:8080".into());
+    // HTTP_WORKERS / HTTP_KEEP_ALIVE_SECS default to actix's own choices: one worker per CPU, 5s keep-alive
+    let http_workers: usize = env::var("HTTP_WORKERS").ok()
+        .map(|v| v.parse().ok().filter(|n| *n > 0).expect("HTTP_WORKERS must be a positive integer"))
+        .unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |n| n.get()));
+    let http_keep_alive_secs: u64 = env::var("HTTP_KEEP_ALIVE_SECS").ok()
+        .map(|v| v.parse().ok().filter(|n| *n > 0).expect("HTTP_KEEP_ALIVE_SECS must be a positive integer"))
+        .unwrap_or(5);
+    let write_behind_enabled = env::var("WRITE_BEHIND").ok().map(|v| v == "1" || v.to_lowercase() == "true").unwrap_or(false);
+    let write_behind_flush_ms: u64 = env::var("WRITE_BEHIND_FLUSH_MS").ok().and_then(|v| v.parse().ok()).unwrap_or(200);
+    let write_behind_batch: usize = env::var("WRITE_BEHIND_BATCH").ok().and_then(|v| v.parse().ok()).unwrap_or(100);
+    let (write_behind_tx, write_behind_rx) = mpsc::channel(write_behind_batch.max(1) * 10);
+    STRING_IDS.store(
+        env::var("STRING_IDS").ok().map(|v| v == "1" || v.to_lowercase() == "true").unwrap_or(false),
+        Ordering::Relaxed,
+    );
+    // SQL_LOG=1 logs each statement's text (never its bound parameters) at debug level, target `sqlx::query`
+    let sql_log = env::var("SQL_LOG").ok().map(|v| v == "1" || v.to_lowercase() == "true").unwrap_or(false);
+    // sqlx's own slow-statement log stays off; `db_timed` reports slow operations (SLOW_QUERY_MS).
+    let connect_options = SqliteConnectOptions::from_str(&database_url)
+        .expect("Invalid DATABASE_URL")
+        .log_statements(if sql_log { LevelFilter::Debug } else { LevelFilter::Off })
+        .log_slow_statements(LevelFilter::Off, Duration::MAX);
+
+    let pool = SqlitePool::connect_with(connect_options).await
+        .expect("Failed to connect to SQLite");
+
+    run_migrations(&pool).await.expect("Migration failed");
+    check_task_schema(&pool).await.expect("Schema check failed");
+    let enforce_unique_titles = env::var("ENFORCE_UNIQUE_TITLES").ok().map(|v| v == "1" || v.to_lowercase() == "true").unwrap_or(false);
+    apply_unique_title_index(&pool, enforce_unique_titles).await
+        .expect("ENFORCE_UNIQUE_TITLES: existing tasks contain duplicate titles");
+
+    let state = web::Data::new(app_state_from_env(pool, write_behind_enabled.then_some(write_behind_tx)));
+
+    if write_behind_enabled {
+        tokio::spawn(run_write_behind(
+            state.clone(),
+            write_behind_rx,
+            Duration::from_millis(write_behind_flush_ms.max(1)),
+            write_behind_batch.max(1),
+        ));
+    }
+
+    // TASK_EXPIRY_SWEEP_SECS: how often tasks past their ttl_seconds are deleted (0 = never)
+    let expiry_sweep_secs: u64 = env::var("TASK_EXPIRY_SWEEP_SECS").ok().and_then(|v| v.parse().ok()).unwrap_or(60);
+    if expiry_sweep_secs > 0 {
+        tokio::spawn(run_expiry_sweeper(state.clone(), Duration::from_secs(expiry_sweep_secs)));
+    }
+
+    {
+        let state = state.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(60));
+            loop {
+                interval.tick().await;
+                if let Some(limiter) = &state.global_rate_limiter {
+                    limiter.cleanup();
+                }
+                state.introspect_rate_limiter.cleanup();
+            }
+        });
+    }
+
+    match bind_addr.strip_prefix("unix:") {
+        Some(socket_path) => println!("Server running on unix socket {}", socket_path),
+        None => println!("Server running at http://{}/", &bind_addr),
+    }
+    println!("JWT enabled: {}", state.jwt_enabled);
+    println!("SQL statement logging: {}", sql_log);
+    println!("Title encryption at rest: {}", state.cipher.is_some());
+    if enforce_unique_titles && state.cipher.is_some() {
+        // Ciphertexts use random nonces, so the index never sees two equal encrypted titles.
+        log::warn!("ENFORCE_UNIQUE_TITLES has no effect on titles encrypted with ENCRYPTION_KEY");
+    }
+    println!("Write-behind creates: {}", write_behind_enabled);
+    println!("HTTP workers: {}, keep-alive: {}s", http_workers, http_keep_alive_secs);
+
+    let shutdown_state = state.clone();
+
+    let server = HttpServer::new(move || {
+        build_app(state.clone())
+    })
+    .workers(http_workers)
+    .keep_alive(Duration::from_secs(http_keep_alive_secs));
+
+    // BIND_ADDR=unix:/path/to.sock serves over a Unix domain socket instead of TCP
+    let server = match bind_addr.strip_prefix("unix:") {
+        #[cfg(unix)]
+        Some(socket_path) => {
+            use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+            // A socket left behind by an unclean shutdown would make bind fail; never remove other files
+            if fs::metadata(socket_path).is_ok_and(|m| m.file_type().is_socket()) {
+                fs::remove_file(socket_path)?;
+            }
+            let mode = env::var("UNIX_SOCKET_MODE").ok()
+                .map(|v| u32::from_str_radix(&v, 8).expect("UNIX_SOCKET_MODE must be octal, e.g. 660"))
+                .unwrap_or(0o660);
+            let server = server.bind_uds(socket_path)?;
+            fs::set_permissions(socket_path, fs::Permissions::from_mode(mode))?;
+            server
+        }
+        #[cfg(not(unix))]
+        Some(_) => {
+            return Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "unix: BIND_ADDR requires a Unix platform"));
+        }
+        None => server.bind(&bind_addr)?,
+    };
+    let result = server.run().await;
+
+    // Write out anything still buffered before exiting
+    if let Some(queue) = &shutdown_state.write_behind {
+        let (ack_tx, ack_rx) = oneshot::channel();
+        if queue.send(WriteBehindMsg::Flush(ack_tx)).await.is_ok() {
+            let _ = ack_rx.await;
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::{header::AUTHORIZATION, StatusCode};
+    use actix_web::test;
+    use serde_json::{json, Value};
+
+    /// A fresh in-memory database with every migration applied. A single connection that is never
+    /// recycled, since each in-memory SQLite connection is its own database.
+    async fn test_pool() -> SqlitePool {
+        let pool = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(1)
+            .idle_timeout(None)
+            .max_lifetime(None)
+            .acquire_timeout(Duration::from_secs(5))
+            .connect("sqlite::memory:").await
+            .unwrap();
+        run_migrations(&pool).await.unwrap();
+        pool
+    }
+
+    /// Settings as with an empty environment; tests adjust fields before building the app.
+    async fn test_state() -> AppState {
+        app_state_from_env(test_pool().await, None)
+    }
+
+    fn create(body: Value) -> test::TestRequest {
+        test::TestRequest::post().uri("/api/tasks").set_json(body)
+    }
+
+    /// The full app over `state`, ready for `test::call_service`.
+    macro_rules! test_app {
+        ($state:expr) => {
+            test::init_service(build_app(web::Data::new($state))).await
+        };
+    }
+
+    /// Creates a task through the API and evaluates to its id.
+    macro_rules! create_task_id {
+        ($app:expr, $body:expr) => {{
+            let resp = test::call_service(&$app, create($body).to_request()).await;
+            assert_eq!(resp.status(), StatusCode::CREATED);
+            let task: Value = test::read_body_json(resp).await;
+            task["id"].as_i64().unwrap()
+        }};
+    }
+
+    /// Logs in as `$user` and evaluates to the signed JWT.
+    macro_rules! login {
+        ($app:expr, $user:expr) => {{
+            let req = test::TestRequest::post().uri("/api/login")
+                .set_json(json!({ "username": $user, "password": "pw" }))
+                .to_request();
+            let body: Value = test::call_and_read_body_json(&$app, req).await;
+            body["token"].as_str().unwrap().to_string()
+        }};
+    }
+
+    fn bearer(token: &str) -> (HeaderName, String) {
+        (AUTHORIZATION, format!("Bearer {}", token))
+    }
+
+    /// JWT auth enabled, with `admins` in `ADMIN_USERS`.
+    async fn jwt_state(admins: &[&str]) -> AppState {
+        let mut state = test_state().await;
+        state.jwt_enabled = true;
+        state.jwt_secret = Some("test-secret".into());
+        state.admin_users = admins.iter().map(|a| a.to_string()).collect();
+        state
+    }
+
+    async fn locked_state(lock: bool) -> AppState {
+        let mut state = test_state().await;
+        state.lock_completed_tasks = lock;
+        state
+    }
+
+    #[actix_web::test]
+    async fn completed_activity_counts_tasks_created_completed() {
+        let app = test_app!(test_state().await);
+        let resp = test::call_service(&app, create(json!({ "title": "done on arrival", "progress": 100 })).to_request()).await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+
+        let req = test::TestRequest::get().uri("/api/tasks/activity?metric=completed&days=1").to_request();
+        let body: Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body[Utc::now().format("%Y-%m-%d").to_string()], 1);
+    }
+
+    #[actix_web::test]
+    async fn locked_task_rejects_put_of_other_fields() {
+        let app = test_app!(locked_state(true).await);
+        let id = create_task_id!(app, json!({ "title": "done", "progress": 100 }));
+        let req = test::TestRequest::put().uri(&format!("/api/tasks/{}", id)).set_json(json!({ "estimate_minutes": 30 })).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::CONFLICT);
+        let req = test::TestRequest::put().uri(&format!("/api/tasks/{}", id)).set_json(json!({ "title": "renamed" })).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::CONFLICT);
+        // Reopening is allowed, and afterwards the task can be edited.
+        let req = test::TestRequest::put().uri(&format!("/api/tasks/{}", id)).set_json(json!({ "completed": false })).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+        let req = test::TestRequest::put().uri(&format!("/api/tasks/{}", id)).set_json(json!({ "estimate_minutes": 30 })).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+    }
+
+    #[actix_web::test]
+    async fn locked_task_rejects_patch_of_other_fields() {
+        let app = test_app!(locked_state(true).await);
+        let id = create_task_id!(app, json!({ "title": "done", "progress": 100 }));
+        let req = test::TestRequest::patch().uri(&format!("/api/tasks/{}", id))
+            .insert_header((CONTENT_TYPE, "application/json-patch+json"))
+            .set_payload(json!([{ "op": "add", "path": "/estimate_minutes", "value": 30 }]).to_string())
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::CONFLICT);
+    }
+
+    #[actix_web::test]
+    async fn locked_task_rejects_batch_update_of_other_fields() {
+        let app = test_app!(locked_state(true).await);
+        let id = create_task_id!(app, json!({ "title": "done", "progress": 100 }));
+        let req = test::TestRequest::post().uri("/api/tasks/batch")
+            .set_json(json!({ "operations": [{ "op": "update", "id": id, "estimate_minutes": 30 }] }))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::CONFLICT);
+    }
+
+    #[actix_web::test]
+    async fn completed_task_is_editable_without_lock() {
+        let app = test_app!(locked_state(false).await);
+        let id = create_task_id!(app, json!({ "title": "done", "progress": 100 }));
+        let req = test::TestRequest::put().uri(&format!("/api/tasks/{}", id)).set_json(json!({ "title": "renamed", "estimate_minutes": 30 })).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+    }
+
+    #[actix_web::test]
+    async fn restore_accepts_a_backup_larger_than_max_json_bytes() {
+        let mut state = jwt_state(&["admin"]).await;
+        state.max_json_bytes = 256;
+        let app = test_app!(state);
+        let token = login!(app, "admin");
+        for i in 0..20 {
+            let req = create(json!({ "title": format!("task number {}", i) })).insert_header(bearer(&token)).to_request();
+            assert_eq!(test::call_service(&app, req).await.status(), StatusCode::CREATED);
+        }
+
+        let req = test::TestRequest::get().uri("/api/admin/backup").insert_header(bearer(&token)).to_request();
+        let dump = test::call_and_read_body(&app, req).await;
+        assert!(dump.len() > 256);
+        let req = test::TestRequest::post().uri("/api/admin/restore?mode=replace")
+            .insert_header(bearer(&token))
+            .insert_header((CONTENT_TYPE, "application/json"))
+            .set_payload(dump)
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body: Value = test::read_body_json(resp).await;
+        assert_eq!(body["restored"]["tasks"], 20);
+
+        // Other JSON bodies still get MAX_JSON_BYTES.
+        let req = create(json!({ "title": "x".repeat(300) })).insert_header(bearer(&token)).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[actix_web::test]
+    async fn batch_validation_error_is_422_with_fields_and_index() {
+        let app = test_app!(test_state().await);
+        let req = test::TestRequest::post().uri("/api/tasks/batch")
+            .set_json(json!({ "operations": [{ "op": "create", "title": "ok" }, { "op": "create", "title": "" }] }))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let body: Value = test::read_body_json(resp).await;
+        assert_eq!(body["index"], 1);
+        assert!(body["fields"]["title"].is_array());
+
+        let req = test::TestRequest::post().uri("/api/tasks/batch")
+            .set_json(json!({ "operations": [{ "op": "create", "title": "ok" }, { "op": "explode" }] }))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let body: Value = test::read_body_json(resp).await;
+        assert_eq!(body["index"], 1);
+    }
+
+    #[actix_web::test]
+    async fn error_responses_get_security_headers() {
+        let app = test_app!(test_state().await);
+        let req = test::TestRequest::post().uri("/api/tasks")
+            .insert_header((CONTENT_ENCODING, "bogus"))
+            .set_json(json!({ "title": "x" }))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        assert_eq!(resp.headers().get(X_CONTENT_TYPE_OPTIONS).unwrap(), "nosniff");
+
+        let resp = test::call_service(&app, test::TestRequest::get().uri("/api/tasks/999").to_request()).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        assert_eq!(resp.headers().get(X_CONTENT_TYPE_OPTIONS).unwrap(), "nosniff");
+        assert_eq!(resp.headers().get(X_FRAME_OPTIONS).unwrap(), "DENY");
+    }
+
+    #[actix_web::test]
+    async fn rate_limited_responses_get_security_headers() {
+        let mut state = test_state().await;
+        state.global_rate_limiter = Some(RateLimiter::new(1, Duration::from_secs(60)));
+        let app = test_app!(state);
+        test::call_service(&app, test::TestRequest::get().uri("/api/tasks").to_request()).await;
+        let resp = test::call_service(&app, test::TestRequest::get().uri("/api/tasks").to_request()).await;
+        assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(resp.headers().get(X_CONTENT_TYPE_OPTIONS).unwrap(), "nosniff");
+    }
+
+    #[actix_web::test]
+    async fn unique_title_create_records_its_change() {
+        let app = test_app!(test_state().await);
+        let req = test::TestRequest::post().uri("/api/tasks?unique_title=true").set_json(json!({ "title": "once" })).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        let task: Value = test::read_body_json(resp).await;
+
+        let body: Value = test::call_and_read_body_json(&app, test::TestRequest::get().uri("/api/tasks/poll?since=0").to_request()).await;
+        assert_eq!(body["changes"][0]["task_id"], task["id"]);
+        assert_eq!(body["changes"][0]["op"], "created");
+
+        let req = test::TestRequest::post().uri("/api/tasks?unique_title=true").set_json(json!({ "title": "once" })).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::CONFLICT);
+        let body: Value = test::read_body_json(resp).await;
+        assert_eq!(body["id"], task["id"]);
+    }
+
+    #[actix_web::test]
+    async fn new_tasks_are_spaced_position_gap_apart() {
+        let app = test_app!(test_state().await);
+        let mut positions = Vec::new();
+        for title in ["a", "b"] {
+            let resp = test::call_service(&app, create(json!({ "title": title })).to_request()).await;
+            let task: Value = test::read_body_json(resp).await;
+            positions.push(task["position"].as_f64().unwrap());
+        }
+        assert_eq!(positions, [POSITION_GAP, 2.0 * POSITION_GAP]);
+    }
+
+    #[actix_web::test]
+    async fn attachment_cap_is_enforced() {
+        let mut state = test_state().await;
+        state.max_attachments_per_task = 2;
+        let app = test_app!(state);
+        let id = create_task_id!(app, json!({ "title": "with files" }));
+        let attach = || test::TestRequest::post().uri(&format!("/api/tasks/{}/attachments", id))
+            .set_json(json!({ "url": "https://example.com/a.png" }))
+            .to_request();
+        assert_eq!(test::call_service(&app, attach()).await.status(), StatusCode::CREATED);
+        assert_eq!(test::call_service(&app, attach()).await.status(), StatusCode::CREATED);
+        assert_eq!(test::call_service(&app, attach()).await.status(), StatusCode::BAD_REQUEST);
+
+        let req = test::TestRequest::get().uri(&format!("/api/tasks/{}/attachments", id)).to_request();
+        let body: Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body.as_array().unwrap().len(), 2);
+    }
+
+    #[actix_web::test]
+    async fn clone_tasks_copies_open_tasks_to_the_new_owner() {
+        let app = test_app!(jwt_state(&["admin"]).await);
+        let alice = login!(app, "alice");
+        for body in [json!({ "title": "read handbook" }), json!({ "title": "set up laptop" }), json!({ "title": "done", "progress": 100 })] {
+            let req = create(body).insert_header(bearer(&alice)).to_request();
+            assert_eq!(test::call_service(&app, req).await.status(), StatusCode::CREATED);
+        }
+        let bob = login!(app, "bob");
+        let req = create(json!({ "title": "bob's own" })).insert_header(bearer(&bob)).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::CREATED);
+        let admin = login!(app, "admin");
+        let clone = |body: Value, token: &str| test::TestRequest::post().uri("/api/admin/clone-tasks")
+            .insert_header(bearer(token))
+            .set_json(body)
+            .to_request();
+
+        let resp = test::call_service(&app, clone(json!({ "from_user": "alice", "to_user": "bob" }), &admin)).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body: Value = test::read_body_json(resp).await;
+        assert_eq!(body["copied"], 2);
+        let req = test::TestRequest::get().uri("/api/tasks").insert_header(bearer(&admin)).to_request();
+        let tasks: Value = test::call_and_read_body_json(&app, req).await;
+        let mut bobs: Vec<&str> = tasks.as_array().unwrap().iter()
+            .filter(|t| t["created_by"] == "bob")
+            .map(|t| t["title"].as_str().unwrap())
+            .collect();
+        bobs.sort();
+        assert_eq!(bobs, ["bob's own", "read handbook", "set up laptop"]);
+
+        let resp = test::call_service(&app, clone(json!({ "from_user": "nobody", "to_user": "bob" }), &admin)).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        let resp = test::call_service(&app, clone(json!({ "from_user": "alice", "to_user": "nobody" }), &admin)).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        let resp = test::call_service(&app, clone(json!({ "from_user": "alice", "to_user": "alice" }), &admin)).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let resp = test::call_service(&app, clone(json!({ "from_user": "alice", "to_user": "carol" }), &alice)).await;
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[actix_web::test]
+    async fn over_long_title_is_a_validation_error() {
+        let mut state = test_state().await;
+        state.max_title_len = 10;
+        let app = test_app!(state);
+        let resp = test::call_service(&app, create(json!({ "title": "x".repeat(11) })).to_request()).await;
+        assert_eq!(resp.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let body: Value = test::read_body_json(resp).await;
+        assert!(body["fields"]["title"].is_array());
+        let resp = test::call_service(&app, create(json!({ "title": "x".repeat(10) })).to_request()).await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+    }
+
+    fn gzip(body: &Value) -> Vec<u8> {
+        use std::io::Write;
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(body.to_string().as_bytes()).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[actix_web::test]
+    async fn gzipped_batch_create_is_decoded_and_capped() {
+        let mut state = test_state().await;
+        state.max_json_bytes = 4096;
+        let app = test_app!(state);
+        let ops: Vec<Value> = (0..20).map(|i| json!({ "op": "create", "title": format!("imported {}", i) })).collect();
+        let req = test::TestRequest::post().uri("/api/tasks/batch")
+            .insert_header((CONTENT_TYPE, "application/json"))
+            .insert_header((CONTENT_ENCODING, "gzip"))
+            .set_payload(gzip(&json!({ "operations": ops })))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body: Value = test::read_body_json(resp).await;
+        assert_eq!(body["results"].as_array().unwrap().len(), 20);
+
+        // Small on the wire, over MAX_JSON_BYTES once decompressed.
+        let bomb = gzip(&json!({ "title": "x".repeat(100_000) }));
+        assert!(bomb.len() < 4096);
+        let req = test::TestRequest::post().uri("/api/tasks")
+            .insert_header((CONTENT_TYPE, "application/json"))
+            .insert_header((CONTENT_ENCODING, "gzip"))
+            .set_payload(bomb)
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+        let req = test::TestRequest::post().uri("/api/tasks")
+            .insert_header((CONTENT_ENCODING, "compress"))
+            .set_json(json!({ "title": "x" }))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    }
+
+    #[actix_web::test]
+    async fn control_characters_in_titles_are_rejected_or_stripped() {
+        let app = test_app!(test_state().await);
+        let resp = test::call_service(&app, create(json!({ "title": "bad\u{0000}title" })).to_request()).await;
+        assert_eq!(resp.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let body: Value = test::read_body_json(resp).await;
+        assert!(body["fields"]["title"].is_array());
+
+        let mut state = test_state().await;
+        state.title_control_chars = ControlCharMode::Strip;
+        let app = test_app!(state);
+        let resp = test::call_service(&app, create(json!({ "title": "bad\u{0000}title\twith tab" })).to_request()).await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        let task: Value = test::read_body_json(resp).await;
+        assert_eq!(task["title"], "badtitle\twith tab");
+    }
+
+    #[actix_web::test]
+    async fn write_behind_creates_eventually_reach_the_database() {
+        let (queue, rx) = mpsc::channel(16);
+        let data = web::Data::new(app_state_from_env(test_pool().await, Some(queue)));
+        tokio::spawn(run_write_behind(data.clone(), rx, Duration::from_millis(10), 100));
+        let app = test::init_service(build_app(data.clone())).await;
+        for title in ["queued one", "queued two"] {
+            let resp = test::call_service(&app, create(json!({ "title": title })).to_request()).await;
+            assert_eq!(resp.status(), StatusCode::ACCEPTED);
+            let body: Value = test::read_body_json(resp).await;
+            assert_eq!(body["status"], "queued");
+        }
+
+        let mut stored = 0;
+        for _ in 0..100 {
+            stored = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM tasks").fetch_one(&data.pool).await.unwrap();
+            if stored == 2 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(stored, 2);
+    }
+
+    #[actix_web::test]
+    async fn ndjson_stream_has_one_task_per_line() {
+        let app = test_app!(test_state().await);
+        for title in ["first", "second", "third"] {
+            assert_eq!(test::call_service(&app, create(json!({ "title": title })).to_request()).await.status(), StatusCode::CREATED);
+        }
+        let req = test::TestRequest::get().uri("/api/tasks/stream.ndjson?sort=id:asc").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers().get(CONTENT_TYPE).unwrap(), "application/x-ndjson");
+        let body = test::read_body(resp).await;
+        let titles: Vec<String> = std::str::from_utf8(&body).unwrap().lines()
+            .map(|line| serde_json::from_str::<Value>(line).unwrap()["title"].as_str().unwrap().to_string())
+            .collect();
+        assert_eq!(titles, ["first", "second", "third"]);
+    }
+
+    #[actix_web::test]
+    async fn overflowing_path_id_is_a_bad_request() {
+        let app = test_app!(test_state().await);
+        let resp = test::call_service(&app, test::TestRequest::get().uri("/api/tasks/99999999999999999999").to_request()).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let body: Value = test::read_body_json(resp).await;
+        assert_eq!(body["error"], "task id out of range");
+
+        let id = create_task_id!(app, json!({ "title": "valid id" }));
+        let resp = test::call_service(&app, test::TestRequest::get().uri(&format!("/api/tasks/{}", id)).to_request()).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let resp = test::call_service(&app, test::TestRequest::get().uri(&format!("/api/tasks/{}", i64::MAX)).to_request()).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[actix_web::test]
+    async fn failed_change_log_insert_rolls_back_the_create() {
+        let data = web::Data::new(test_state().await);
+        let app = test::init_service(build_app(data.clone())).await;
+        // The task insert succeeds, then recording its change fails.
+        sqlx::query("DROP TABLE task_changes").execute(&data.pool).await.unwrap();
+        let resp = test::call_service(&app, create(json!({ "title": "half written" })).to_request()).await;
+        assert_eq!(resp.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let stored: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM tasks").fetch_one(&data.pool).await.unwrap();
+        assert_eq!(stored, 0);
+    }
+
+    #[actix_web::test]
+    async fn patch_of_completed_task_depends_on_lock_mode() {
+        let patch = |id: i64, ops: Value| test::TestRequest::patch().uri(&format!("/api/tasks/{}", id))
+            .insert_header((CONTENT_TYPE, "application/json-patch+json"))
+            .set_payload(ops.to_string())
+            .to_request();
+        let rename = json!([{ "op": "replace", "path": "/title", "value": "renamed" }]);
+
+        let app = test_app!(locked_state(true).await);
+        let id = create_task_id!(app, json!({ "title": "done", "progress": 100 }));
+        assert_eq!(test::call_service(&app, patch(id, rename.clone())).await.status(), StatusCode::CONFLICT);
+        let reopen = json!([{ "op": "replace", "path": "/completed", "value": false }]);
+        assert_eq!(test::call_service(&app, patch(id, reopen)).await.status(), StatusCode::OK);
+        assert_eq!(test::call_service(&app, patch(id, rename.clone())).await.status(), StatusCode::OK);
+
+        let app = test_app!(locked_state(false).await);
+        let id = create_task_id!(app, json!({ "title": "done", "progress": 100 }));
+        assert_eq!(test::call_service(&app, patch(id, rename)).await.status(), StatusCode::OK);
+    }
+
+    #[actix_web::test]
+    async fn malformed_body_is_400_and_invalid_body_is_422() {
+        let app = test_app!(test_state().await);
+        let req = test::TestRequest::post().uri("/api/tasks")
+            .insert_header((CONTENT_TYPE, "application/json"))
+            .set_payload("{\"title\": ")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let body: Value = test::read_body_json(resp).await;
+        assert!(body["error"].is_string());
+        assert!(body.get("fields").is_none());
+
+        let resp = test::call_service(&app, create(json!({ "title": "" })).to_request()).await;
+        assert_eq!(resp.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let body: Value = test::read_body_json(resp).await;
+        assert!(body["fields"]["title"].is_array());
+    }
+
+    #[actix_web::test]
+    async fn revoked_tokens_stop_working() {
+        let app = test_app!(jwt_state(&["admin"]).await);
+        let bob = login!(app, "bob");
+        let admin = login!(app, "admin");
+        let req = create(json!({ "title": "before" })).insert_header(bearer(&bob)).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::CREATED);
+
+        let req = test::TestRequest::post().uri("/api/admin/users/bob/revoke-tokens").insert_header(bearer(&bob)).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::FORBIDDEN);
+        let req = test::TestRequest::post().uri("/api/admin/users/bob/revoke-tokens").insert_header(bearer(&admin)).to_request();
+        assert!(test::call_service(&app, req).await.status().is_success());
+
+        let req = create(json!({ "title": "after" })).insert_header(bearer(&bob)).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::UNAUTHORIZED);
+        let req = create(json!({ "title": "other user" })).insert_header(bearer(&admin)).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::CREATED);
+
+        // Revocation has second granularity, so a fresh login works from the next second on.
+        tokio::time::sleep(Duration::from_millis(1100)).await;
+        let bob = login!(app, "bob");
+        let req = create(json!({ "title": "after login" })).insert_header(bearer(&bob)).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::CREATED);
+    }
+
+    #[actix_web::test]
+    async fn list_honours_prefer_return() {
+        let app = test_app!(test_state().await);
+        let id = create_task_id!(app, json!({ "title": "preferred" }));
+
+        let req = test::TestRequest::get().uri("/api/tasks").insert_header(("Prefer", "return=minimal")).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.headers().get("Preference-Applied").unwrap(), "return=minimal");
+        let body: Value = test::read_body_json(resp).await;
+        assert_eq!(body, json!([id]));
+
+        let req = test::TestRequest::get().uri("/api/tasks").insert_header(("Prefer", "return=representation")).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.headers().get("Preference-Applied").unwrap(), "return=representation");
+        let body: Value = test::read_body_json(resp).await;
+        assert_eq!(body[0]["title"], "preferred");
+
+        let resp = test::call_service(&app, test::TestRequest::get().uri("/api/tasks").to_request()).await;
+        assert!(resp.headers().get("Preference-Applied").is_none());
+        let body: Value = test::read_body_json(resp).await;
+        assert_eq!(body[0]["id"], id);
+        assert_eq!(body[0]["title"], "preferred");
+    }
+
+    #[actix_web::test]
+    async fn tokens_must_match_configured_issuer_and_audience() {
+        let mut state = jwt_state(&[]).await;
+        state.jwt_issuer = Some("tasks-auth".into());
+        state.jwt_audience = Some("tasks-api".into());
+        let app = test_app!(state);
+        let sign = |iss: Option<&str>, aud: Option<&str>| {
+            let now = Utc::now().timestamp() as usize;
+            let claims = Claims {
+                sub: "alice".into(),
+                exp: now + 3600,
+                iat: now,
+                iss: iss.map(str::to_string),
+                aud: aud.map(str::to_string),
+            };
+            encode(&Header::default(), &claims, &EncodingKey::from_secret(b"test-secret")).unwrap()
+        };
+        let status = |token: String| {
+            let req = create(json!({ "title": "t" })).insert_header(bearer(&token)).to_request();
+            async { test::call_service(&app, req).await.status() }
+        };
+
+        assert_eq!(status(login!(app, "alice")).await, StatusCode::CREATED);
+        assert_eq!(status(sign(Some("tasks-auth"), Some("tasks-api"))).await, StatusCode::CREATED);
+        assert_eq!(status(sign(Some("tasks-auth"), Some("other-api"))).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(status(sign(Some("tasks-auth"), None)).await, StatusCode::UNAUTHORIZED);
+        assert_eq!(status(sign(Some("someone-else"), Some("tasks-api"))).await, StatusCode::UNAUTHORIZED);
+    }
+
+    #[actix_web::test]
+    async fn strict_json_rejects_unknown_fields() {
+        let app = test_app!(test_state().await);
+        let resp = test::call_service(&app, create(json!({ "title": "lenient", "titel": "typo" })).to_request()).await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+
+        let mut state = test_state().await;
+        state.strict_json = true;
+        let app = test_app!(state);
+        let resp = test::call_service(&app, create(json!({ "title": "strict", "titel": "typo" })).to_request()).await;
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        let body: Value = test::read_body_json(resp).await;
+        assert!(body["error"].as_str().unwrap().starts_with("unknown fields: titel"));
+
+        let id = create_task_id!(app, json!({ "title": "strict" }));
+        let req = test::TestRequest::put().uri(&format!("/api/tasks/{}", id)).set_json(json!({ "complete": true })).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_web::test]
+    async fn pending_list_uses_the_partial_index() {
+        let state = test_state().await;
+        let query = web::Query::<ListQuery>::from_query("completed=false").unwrap().into_inner();
+        let mut qb = QueryBuilder::new("EXPLAIN QUERY PLAN SELECT id FROM tasks");
+        push_list_filters(&mut qb, &query, &state).unwrap();
+        push_list_order(&mut qb, &query, &state).unwrap();
+        let plan: Vec<String> = qb.build().fetch_all(&state.pool).await.unwrap()
+            .iter().map(|row| row.get::<String, _>("detail")).collect();
+        assert!(plan.iter().any(|step| step.contains("idx_tasks_pending_created_at")), "plan: {:?}", plan);
+    }
+
+    #[actix_web::test]
+    async fn changed_applied_migration_stops_startup() {
+        let pool = test_pool().await;
+        run_migrations(&pool).await.unwrap();
+        // Same as editing 001 after it ran: the recorded checksum no longer matches the file.
+        sqlx::query("UPDATE _migrations SET checksum = 'edited' WHERE version = 1").execute(&pool).await.unwrap();
+        let err = run_migrations(&pool).await.unwrap_err();
+        assert!(err.to_string().contains("migration 1 (migrations/001_init.sql) was modified after it was applied"), "{}", err);
+    }
+
+    #[actix_web::test]
+    async fn list_returns_304_for_a_matching_etag() {
+        let app = test_app!(test_state().await);
+        let id = create_task_id!(app, json!({ "title": "cached" }));
+        create_task_id!(app, json!({ "title": "also cached" }));
+        let resp = test::call_service(&app, test::TestRequest::get().uri("/api/tasks").to_request()).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let etag = resp.headers().get("ETag").unwrap().to_str().unwrap().to_string();
+
+        let req = test::TestRequest::get().uri("/api/tasks").insert_header(("If-None-Match", etag.as_str())).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::NOT_MODIFIED);
+        assert!(test::read_body(resp).await.is_empty());
+
+        let req = test::TestRequest::delete().uri(&format!("/api/tasks/{}", id)).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::NO_CONTENT);
+        let req = test::TestRequest::get().uri("/api/tasks").insert_header(("If-None-Match", etag.as_str())).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+    }
+
+    #[actix_web::test]
+    async fn trailing_slashes_route_like_the_bare_path() {
+        let mut state = test_state().await;
+        state.trim_trailing_slash = true;
+        let app = test_app!(state);
+        let req = test::TestRequest::post().uri("/api/tasks/").set_json(json!({ "title": "slashed" })).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        let task: Value = test::read_body_json(resp).await;
+        let id = task["id"].as_i64().unwrap();
+
+        for uri in ["/api/tasks", "/api/tasks/"] {
+            let body: Value = test::call_and_read_body_json(&app, test::TestRequest::get().uri(uri).to_request()).await;
+            assert_eq!(body[0]["id"], id, "{}", uri);
+        }
+        for uri in [format!("/api/tasks/{}", id), format!("/api/tasks/{}/", id)] {
+            let body: Value = test::call_and_read_body_json(&app, test::TestRequest::get().uri(&uri).to_request()).await;
+            assert_eq!(body["title"], "slashed", "{}", uri);
+        }
+        for uri in ["/api/tasks/count", "/api/tasks/count/"] {
+            let body: Value = test::call_and_read_body_json(&app, test::TestRequest::get().uri(uri).to_request()).await;
+            assert_eq!(body["count"], 1, "{}", uri);
+        }
+    }
+
+    #[actix_web::test]
+    async fn restricted_users_can_only_edit_allowed_fields() {
+        let mut state = jwt_state(&["admin"]).await;
+        state.user_editable_fields = Some(vec!["completed"]);
+        let app = test_app!(state);
+        let (alice, admin) = (login!(app, "alice"), login!(app, "admin"));
+        let mut ids = Vec::new();
+        for title in ["restricted", "anchor"] {
+            let resp = test::call_service(&app, create(json!({ "title": title })).insert_header(bearer(&alice)).to_request()).await;
+            let task: Value = test::read_body_json(resp).await;
+            ids.push(task["id"].as_i64().unwrap());
+        }
+        let (id, anchor) = (ids[0], ids[1]);
+        let put = |body: Value, token: &str| test::TestRequest::put().uri(&format!("/api/tasks/{}", id))
+            .insert_header(bearer(token))
+            .set_json(body)
+            .to_request();
+
+        assert_eq!(test::call_service(&app, put(json!({ "title": "renamed" }), &alice)).await.status(), StatusCode::FORBIDDEN);
+        let req = test::TestRequest::patch().uri(&format!("/api/tasks/{}", id))
+            .insert_header(bearer(&alice))
+            .insert_header((CONTENT_TYPE, "application/json-patch+json"))
+            .set_payload(json!([{ "op": "replace", "path": "/title", "value": "renamed" }]).to_string())
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::FORBIDDEN);
+        let req = test::TestRequest::post().uri(&format!("/api/tasks/{}/move", id))
+            .insert_header(bearer(&alice))
+            .set_json(json!({ "after_id": anchor }))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::FORBIDDEN);
+        let req = test::TestRequest::post().uri(&format!("/api/tasks/{}/snooze", id))
+            .insert_header(bearer(&alice))
+            .set_json(json!({ "until": (Utc::now() + chrono::Duration::hours(1)).to_rfc3339() }))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::FORBIDDEN);
+
+        assert_eq!(test::call_service(&app, put(json!({ "completed": true }), &alice)).await.status(), StatusCode::OK);
+        let resp = test::call_service(&app, put(json!({ "title": "renamed" }), &admin)).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let task: Value = test::read_body_json(resp).await;
+        assert_eq!(task["title"], "renamed");
+    }
+
+    #[actix_web::test]
+    async fn locked_task_cannot_be_moved_or_snoozed() {
+        let app = test_app!(locked_state(true).await);
+        let id = create_task_id!(app, json!({ "title": "done", "progress": 100 }));
+        let anchor = create_task_id!(app, json!({ "title": "anchor" }));
+        let req = test::TestRequest::post().uri(&format!("/api/tasks/{}/move", id)).set_json(json!({ "after_id": anchor })).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::CONFLICT);
+        let req = test::TestRequest::post().uri(&format!("/api/tasks/{}/snooze", id))
+            .set_json(json!({ "until": (Utc::now() + chrono::Duration::hours(1)).to_rfc3339() }))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::CONFLICT);
+        let req = test::TestRequest::post().uri(&format!("/api/tasks/{}/move", anchor)).set_json(json!({ "before_id": id })).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+    }
+
+    #[actix_web::test]
+    async fn update_records_the_editing_user() {
+        let app = test_app!(jwt_state(&[]).await);
+        let (alice, bob) = (login!(app, "alice"), login!(app, "bob"));
+        let resp = test::call_service(&app, create(json!({ "title": "shared" })).insert_header(bearer(&alice)).to_request()).await;
+        let task: Value = test::read_body_json(resp).await;
+        assert_eq!(task["updated_by"], "alice");
+
+        let req = test::TestRequest::put().uri(&format!("/api/tasks/{}", task["id"]))
+            .insert_header(bearer(&bob))
+            .set_json(json!({ "completed": true }))
+            .to_request();
+        let task: Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(task["created_by"], "alice");
+        assert_eq!(task["updated_by"], "bob");
+    }
+
+    #[actix_web::test]
+    async fn write_behind_drops_only_the_failing_row() {
+        let state = test_state().await;
+        apply_unique_title_index(&state.pool, true).await.unwrap();
+        let pending = |title: &str, n: u32| PendingTask {
+            title: title.into(),
+            progress: 0,
+            estimate_minutes: None,
+            expires_at: None,
+            actor: "alice".into(),
+            provisional_id: format!("wb-test-{}", n),
+        };
+        let mut batch = vec![pending("first", 0), pending("dup", 1), pending("DUP", 2), pending("last", 3)];
+        flush_pending(&state, &mut batch).await;
+
+        let titles: Vec<String> = sqlx::query_scalar("SELECT title FROM tasks ORDER BY id").fetch_all(&state.pool).await.unwrap();
+        assert_eq!(titles, ["first", "dup", "last"]);
+        let changes: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM task_changes").fetch_one(&state.pool).await.unwrap();
+        assert_eq!(changes, 3);
+    }
+
+    #[actix_web::test]
+    async fn list_cache_entries_are_per_user() {
+        let mut state = jwt_state(&[]).await;
+        state.list_cache = Some(ListCache::new(Duration::from_secs(60)));
+        let app = test_app!(state);
+        let alice = login!(app, "alice");
+        let bob = login!(app, "bob");
+        let list = |token: &str| test::TestRequest::get().uri("/api/tasks").insert_header(bearer(token)).to_request();
+
+        assert_eq!(test::call_service(&app, list(&alice)).await.headers().get("X-Cache").unwrap(), "MISS");
+        assert_eq!(test::call_service(&app, list(&alice)).await.headers().get("X-Cache").unwrap(), "HIT");
+        assert_eq!(test::call_service(&app, list(&bob)).await.headers().get("X-Cache").unwrap(), "MISS");
+    }
+
+    #[actix_web::test]
+    async fn merge_shows_up_in_the_change_feed() {
+        let app = test_app!(test_state().await);
+        let target = create_task_id!(app, json!({ "title": "write report" }));
+        let source = create_task_id!(app, json!({ "title": "write the report" }));
+        let req = test::TestRequest::get().uri("/api/tasks/poll?since=0").to_request();
+        let before: Value = test::call_and_read_body_json(&app, req).await;
+
+        let req = test::TestRequest::post().uri(&format!("/api/tasks/{}/merge", target))
+            .set_json(json!({ "source_id": source }))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::OK);
+
+        let req = test::TestRequest::get().uri(&format!("/api/tasks/poll?since={}", before["change_id"])).to_request();
+        let body: Value = test::call_and_read_body_json(&app, req).await;
+        let changes: Vec<(i64, &str)> = body["changes"].as_array().unwrap().iter()
+            .map(|c| (c["task_id"].as_i64().unwrap(), c["op"].as_str().unwrap()))
+            .collect();
+        assert_eq!(changes, [(source, "deleted"), (target, "updated")]);
+    }
+
+    #[actix_web::test]
+    async fn restore_merge_aborts_on_a_not_null_violation() {
+        let app = test_app!(jwt_state(&["admin"]).await);
+        let token = login!(app, "admin");
+        for title in ["first", "second"] {
+            let req = create(json!({ "title": title })).insert_header(bearer(&token)).to_request();
+            assert_eq!(test::call_service(&app, req).await.status(), StatusCode::CREATED);
+        }
+        let req = test::TestRequest::get().uri("/api/admin/backup").insert_header(bearer(&token)).to_request();
+        let mut dump: Value = serde_json::from_slice(&test::call_and_read_body(&app, req).await).unwrap();
+        dump["tables"]["tasks"][1]["title"] = Value::Null;
+
+        let req = test::TestRequest::post().uri("/api/admin/restore?mode=merge")
+            .insert_header(bearer(&token))
+            .set_json(dump)
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), StatusCode::BAD_REQUEST);
+        let body: Value = test::call_and_read_body_json(&app, test::TestRequest::get().uri("/api/tasks/count").to_request()).await;
+        assert_eq!(body["count"], 2);
+    }
 }